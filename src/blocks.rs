@@ -0,0 +1,113 @@
+use bevy::color::{
+    Srgba,
+    palettes::tailwind::{AMBER_700, GREEN_700, NEUTRAL_200, NEUTRAL_950, STONE_500},
+};
+
+/// Static properties shared by every tile and item with a given id
+pub struct BlockDef {
+    /// Name shown to the player
+    pub name: &'static str,
+    /// Minimum tool tier needed to break this block at full speed
+    pub required_tier: u8,
+    /// Tier this item counts as when it's held as a mining tool
+    pub tool_tier: u8,
+    /// Items given to the player when this block is broken
+    pub drops: &'static [BlockDrop],
+    /// Color the block fades toward as it's broken. Dark blocks should crack toward a light color
+    /// and light blocks toward a dark one so the progress is always visible.
+    pub crack_color: Srgba,
+    /// Color that stands for this block in the toolbar, in particles and while it's being broken
+    pub color: Srgba,
+    /// Stand-in for color in the colorblind palette. These are picked so that no two blocks are
+    /// easily confused with red-green color blindness.
+    pub colorblind_color: Srgba,
+}
+
+impl BlockDef {
+    /// Return the color that stands for this block in whichever palette is in use
+    pub fn display_color(&self, colorblind: bool) -> Srgba {
+        match colorblind {
+            true => self.colorblind_color,
+            false => self.color,
+        }
+    }
+}
+
+/// One entry in a block's drop table
+pub struct BlockDrop {
+    pub item_id: usize,
+    /// Probability that this entry drops at all, from 0 to 1
+    pub chance: f64,
+    /// Inclusive range of how many items drop when it does
+    pub min_count: usize,
+    pub max_count: usize,
+}
+
+impl BlockDrop {
+    /// A drop that always gives exactly one of an item
+    const fn one(item_id: usize) -> Self {
+        BlockDrop {
+            item_id,
+            chance: 1.,
+            min_count: 1,
+            max_count: 1,
+        }
+    }
+}
+
+/// Tier used for mining when nothing is held
+pub const HAND_TIER: u8 = 0;
+
+/// Block definitions, indexed by block id
+// Ids line up with TileData's fg_id/bg_id and ItemStack's item_id
+const BLOCKS: [BlockDef; 4] = [
+    // Air
+    BlockDef {
+        name: "Air",
+        required_tier: 0,
+        tool_tier: HAND_TIER,
+        drops: &[],
+        crack_color: NEUTRAL_950,
+        color: NEUTRAL_200,
+        colorblind_color: NEUTRAL_200,
+    },
+    // Dirt
+    BlockDef {
+        name: "Dirt",
+        required_tier: 0,
+        tool_tier: HAND_TIER,
+        drops: &[BlockDrop::one(1)],
+        crack_color: NEUTRAL_950,
+        color: AMBER_700,
+        // Vermillion
+        colorblind_color: Srgba::rgb_u8(0xD5, 0x5E, 0x00),
+    },
+    // Grass
+    // Digging up grass only leaves the dirt underneath
+    BlockDef {
+        name: "Grass",
+        required_tier: 0,
+        tool_tier: HAND_TIER,
+        drops: &[BlockDrop::one(1)],
+        crack_color: NEUTRAL_950,
+        color: GREEN_700,
+        // Sky blue
+        colorblind_color: Srgba::rgb_u8(0x56, 0xB4, 0xE9),
+    },
+    // Stone
+    BlockDef {
+        name: "Stone",
+        required_tier: 1,
+        // A chunk of stone is hard enough to work more stone with
+        tool_tier: 1,
+        drops: &[BlockDrop::one(3)],
+        crack_color: NEUTRAL_200,
+        color: STONE_500,
+        colorblind_color: STONE_500,
+    },
+];
+
+/// Look up the definition for a block id. Unknown ids are treated as air.
+pub fn block(id: usize) -> &'static BlockDef {
+    BLOCKS.get(id).unwrap_or(&BLOCKS[0])
+}
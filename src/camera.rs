@@ -1,13 +1,65 @@
-use bevy::{input::mouse::AccumulatedMouseScroll, prelude::*};
+use avian2d::prelude::LinearVelocity;
+use bevy::{input::mouse::AccumulatedMouseScroll, prelude::*, window::PrimaryWindow};
+use serde::{Deserialize, Serialize};
 
-use crate::player::Player;
+use crate::{player::Player, terrain::map_world_rect, ui::TOOLBAR_SCROLL_MODIFIERS};
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_camera)
-            .add_systems(Update, (track_camera_to_player, zoom_camera));
+        app.init_resource::<CameraSettings>()
+            .init_resource::<CameraShake>()
+            .add_systems(Startup, setup_camera)
+            .add_systems(
+                Update,
+                (
+                    // Shake is taken off before the camera moves and put back afterwards, so
+                    // following never chases the shaken position
+                    (
+                        remove_camera_shake,
+                        toggle_fly_camera,
+                        track_camera_to_player,
+                        fly_camera,
+                        toggle_zoom_to_fit,
+                        apply_camera_shake,
+                    )
+                        .chain(),
+                    zoom_camera,
+                ),
+            );
+    }
+}
+
+/// Tunable camera behavior, read every frame so changes take effect immediately
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Roughly how many seconds the camera takes to catch up to the player
+    pub catch_up_time: f32,
+    pub zoom_speed: f32,
+    /// Smallest and largest allowed orthographic projection scale
+    pub zoom_min: f32,
+    pub zoom_max: f32,
+    /// How far ahead of the player the camera aims per unit of horizontal speed, and the most it
+    /// will ever aim ahead, both in world units
+    pub lookahead_per_speed: f32,
+    pub lookahead_max: f32,
+    /// How fast the detached fly camera moves, in world units per second
+    pub fly_speed: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            catch_up_time: 0.33,
+            zoom_speed: 1.0,
+            zoom_min: 0.05,
+            zoom_max: 0.2,
+            lookahead_per_speed: 0.4,
+            lookahead_max: 6.,
+            fly_speed: 40.,
+        }
     }
 }
 
@@ -29,41 +81,208 @@ fn setup_camera(mut commands: Commands) {
     ));
 }
 
-const CATCH_UP_TIME: f32 = 0.33;
 fn track_camera_to_player(
-    mut camera: Single<&mut Transform, (With<Camera>, Without<Player>)>,
-    player: Single<&Transform, With<Player>>,
+    mut camera: Single<
+        &mut Transform,
+        (
+            With<Camera>,
+            Without<Player>,
+            Without<ZoomedToFit>,
+            Without<FlyCamera>,
+        ),
+    >,
+    player: Single<(&Transform, &LinearVelocity), With<Player>>,
+    settings: Res<CameraSettings>,
     time: Res<Time>,
 ) {
+    let (player_transform, player_vel) = player.into_inner();
+
+    // Aim ahead of the player in the direction they're moving so more of what's coming is on
+    // screen. The camera eases toward its target, so this swings over smoothly on a reversal.
+    let lookahead = (player_vel.x * settings.lookahead_per_speed)
+        .clamp(-settings.lookahead_max, settings.lookahead_max);
     let target = Vec3::new(
-        player.translation.x,
-        player.translation.y,
+        player_transform.translation.x + lookahead,
+        player_transform.translation.y,
         camera.translation.z,
     );
+    // Cap the interpolation factor so very short catch up times snap rather than overshoot
     camera.translation = camera
         .translation
-        .lerp(target, time.delta_secs() / CATCH_UP_TIME);
+        .lerp(target, (time.delta_secs() / settings.catch_up_time).min(1.));
 }
 
-const ZOOM_SPEED: f32 = 1.0;
-const ZOOM_MIN: f32 = 0.05;
-const ZOOM_MAX: f32 = 0.2;
 fn zoom_camera(
-    projection: Single<&mut Projection, With<Camera>>,
+    projection: Single<&mut Projection, (With<Camera>, Without<ZoomedToFit>)>,
     scroll_input: Res<AccumulatedMouseScroll>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<CameraSettings>,
     time: Res<Time>,
 ) {
+    // Scrolling with a modifier held cycles the toolbar instead
+    if keyboard.any_pressed(TOOLBAR_SCROLL_MODIFIERS) {
+        return;
+    }
+
     match projection.into_inner().into_inner() {
         Projection::Orthographic(ortho_projection) => {
             // Zoom in when scrolling up
-            let zoom_delta = -scroll_input.delta.y * ZOOM_SPEED * time.delta_secs();
+            let zoom_delta = -scroll_input.delta.y * settings.zoom_speed * time.delta_secs();
 
             // Logarithmic (multiplicative) zoom scaling
             let zoom_scale = 1. + zoom_delta;
 
             ortho_projection.scale =
-                (ortho_projection.scale * zoom_scale).clamp(ZOOM_MIN, ZOOM_MAX);
+                (ortho_projection.scale * zoom_scale).clamp(settings.zoom_min, settings.zoom_max);
         }
         _ => {}
     }
 }
+
+/// Present on the camera while it's zoomed out to frame the whole map, remembering the zoom to go
+/// back to afterwards
+#[derive(Component)]
+struct ZoomedToFit {
+    previous_scale: f32,
+}
+
+/// Toggle between following the player and framing the entire map, which is handy for checking
+/// terrain generation
+fn toggle_zoom_to_fit(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    camera: Single<
+        (
+            Entity,
+            &mut Transform,
+            &mut Projection,
+            Option<&ZoomedToFit>,
+        ),
+        With<MainCamera>,
+    >,
+    window: Single<&Window, With<PrimaryWindow>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyM) {
+        return;
+    }
+
+    let (camera_entity, mut transform, mut projection, zoomed_to_fit) = camera.into_inner();
+    let Projection::Orthographic(ortho_projection) = &mut *projection else {
+        return;
+    };
+
+    // Going back just restores the old zoom, and tracking picks the player back up on its own
+    if let Some(zoomed_to_fit) = zoomed_to_fit {
+        ortho_projection.scale = zoomed_to_fit.previous_scale;
+        commands.entity(camera_entity).remove::<ZoomedToFit>();
+        return;
+    }
+
+    // At scale 1 one world unit is one logical pixel, so pick whichever scale fits the map along
+    // its tighter dimension
+    let map = map_world_rect();
+    commands.entity(camera_entity).insert(ZoomedToFit {
+        previous_scale: ortho_projection.scale,
+    });
+    ortho_projection.scale = (map.width() / window.width()).max(map.height() / window.height());
+    transform.translation = map.center().extend(transform.translation.z);
+}
+
+/// Present on the camera while it's detached from the player and flying around freely, for
+/// debugging and screenshots
+#[derive(Component)]
+struct FlyCamera;
+
+/// Run condition for whether the camera is in fly mode, so player controls can stand down while
+/// the movement keys are steering the camera
+pub fn flying(camera: Query<(), (With<MainCamera>, With<FlyCamera>)>) -> bool {
+    !camera.is_empty()
+}
+
+/// Toggle the fly camera. Turning it off snaps the camera straight back onto the player instead of
+/// easing over from wherever it flew to.
+fn toggle_fly_camera(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    camera: Single<(Entity, &mut Transform, Has<FlyCamera>), (With<MainCamera>, Without<Player>)>,
+    player: Single<&Transform, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+
+    let (camera_entity, mut transform, flying) = camera.into_inner();
+    if flying {
+        commands.entity(camera_entity).remove::<FlyCamera>();
+        transform.translation = player.translation.xy().extend(transform.translation.z);
+    } else {
+        commands.entity(camera_entity).insert(FlyCamera);
+    }
+}
+
+/// Move the fly camera with WASD or the arrow keys
+fn fly_camera(
+    mut camera: Single<&mut Transform, (With<MainCamera>, With<FlyCamera>)>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<CameraSettings>,
+    time: Res<Time>,
+) {
+    let pressed = |keys: [KeyCode; 2]| keyboard.any_pressed(keys) as i8;
+    let direction = Vec2::new(
+        f32::from(
+            pressed([KeyCode::KeyD, KeyCode::ArrowRight])
+                - pressed([KeyCode::KeyA, KeyCode::ArrowLeft]),
+        ),
+        f32::from(
+            pressed([KeyCode::KeyW, KeyCode::ArrowUp])
+                - pressed([KeyCode::KeyS, KeyCode::ArrowDown]),
+        ),
+    );
+    camera.translation +=
+        (direction.normalize_or_zero() * settings.fly_speed * time.delta_secs()).extend(0.);
+}
+
+/// How much the camera is shaking, from 0 (still) to 1 (as violent as it gets). Events add trauma,
+/// and it wears off on its own.
+#[derive(Resource, Default)]
+pub struct CameraShake {
+    pub trauma: f32,
+    /// Offset currently applied on top of the camera's real position
+    offset: Vec2,
+}
+
+impl CameraShake {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0., 1.);
+    }
+}
+
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.5;
+/// Furthest the camera can be pushed from its real position, in world units
+const SHAKE_MAX_OFFSET: f32 = 1.5;
+/// How quickly the shake wobbles back and forth, in radians per second
+const SHAKE_FREQUENCY: f32 = 40.;
+
+fn remove_camera_shake(
+    mut camera: Single<&mut Transform, With<MainCamera>>,
+    shake: Res<CameraShake>,
+) {
+    camera.translation -= shake.offset.extend(0.);
+}
+
+/// Push the camera off its real position in proportion to the current trauma
+fn apply_camera_shake(
+    mut camera: Single<&mut Transform, With<MainCamera>>,
+    mut shake: ResMut<CameraShake>,
+    time: Res<Time>,
+) {
+    shake.trauma = (shake.trauma - TRAUMA_DECAY * time.delta_secs()).max(0.);
+
+    // Squaring trauma makes small bumps subtle while big hits still feel big. The two axes wobble
+    // at slightly different rates so the shake doesn't just move along a diagonal.
+    let t = time.elapsed_secs() * SHAKE_FREQUENCY;
+    let wobble = Vec2::new(t.sin(), (t * 1.3 + 1.).sin());
+    shake.offset = wobble * shake.trauma.powi(2) * SHAKE_MAX_OFFSET;
+    camera.translation += shake.offset.extend(0.);
+}
@@ -0,0 +1,159 @@
+use avian2d::prelude::*;
+use bevy::{color::palettes::tailwind::STONE_300, prelude::*, window::PrimaryWindow};
+
+use crate::{
+    ZLayer,
+    player::Player,
+    terrain::{WorldGenerated, WorldTiles},
+    ui::UiPointer,
+};
+
+pub struct GrapplingHookPlugin;
+
+impl Plugin for GrapplingHookPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HookMode>().add_systems(
+            Update,
+            (
+                release_hook_in_new_world.run_if(on_event::<WorldGenerated>),
+                toggle_hook_mode,
+                fire_hook,
+                pull_player,
+                draw_hook_line,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Whether right click fires the grappling hook instead of placing blocks
+#[derive(Resource, Default)]
+pub struct HookMode(pub bool);
+
+/// Attached to the player while the hook is latched onto a tile
+#[derive(Component)]
+struct GrappleHook {
+    anchor: Vec2,
+}
+
+/// Marker component for the sprite drawn between the player and the hook's anchor
+#[derive(Component)]
+struct HookLine;
+
+fn toggle_hook_mode(keyboard: Res<ButtonInput<KeyCode>>, mut hook_mode: ResMut<HookMode>) {
+    if keyboard.just_pressed(KeyCode::KeyG) {
+        hook_mode.0 = !hook_mode.0;
+    }
+}
+
+const HOOK_RANGE: f32 = 20.;
+/// Fire the hook toward the cursor and latch onto the first solid tile in range, if any
+fn fire_hook(
+    mut commands: Commands,
+    hook_mode: Res<HookMode>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    player: Single<(Entity, &Transform, Has<GrappleHook>), With<Player>>,
+    world_tiles: WorldTiles,
+    ui_pointer: UiPointer,
+) {
+    if !hook_mode.0 || !mouse.just_pressed(MouseButton::Right) || ui_pointer.over_ui() {
+        return;
+    }
+
+    // Get the mouse position and convert to world space coordinates
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+    let Ok(world_pos) = camera.0.viewport_to_world_2d(camera.1, cursor_pos) else {
+        return;
+    };
+
+    // Cast from the player toward the cursor. Misses don't do anything.
+    let (player_entity, player_transform, already_hooked) = player.into_inner();
+    let origin = player_transform.translation.truncate();
+    let Ok(direction) = Dir2::new(world_pos - origin) else {
+        return;
+    };
+    let Some(anchor) = world_tiles.raycast_solid(origin, direction, HOOK_RANGE) else {
+        return;
+    };
+
+    // Re-firing while hooked just moves the anchor, so only spawn a line for a fresh hook
+    commands
+        .entity(player_entity)
+        .insert(GrappleHook { anchor });
+    if !already_hooked {
+        commands.spawn((
+            HookLine,
+            Sprite::from_color(STONE_300, Vec2::ZERO),
+            Transform::default(),
+        ));
+    }
+}
+
+const HOOK_PULL_ACCELERATION: f32 = 80.;
+const HOOK_RELEASE_DISTANCE: f32 = 1.5;
+/// Pull a hooked player toward the anchor until they arrive or let go of the hook
+fn pull_player(
+    mut commands: Commands,
+    hook_mode: Res<HookMode>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    time: Res<Time>,
+    player: Single<(Entity, &Transform, &mut LinearVelocity, &GrappleHook), With<Player>>,
+    lines: Query<Entity, With<HookLine>>,
+) {
+    let (player_entity, player_transform, mut player_vel, hook) = player.into_inner();
+    let to_anchor = hook.anchor - player_transform.translation.truncate();
+
+    // Release the hook once the player is close enough, lets go of right click, or leaves hook
+    // mode
+    if to_anchor.length() < HOOK_RELEASE_DISTANCE
+        || mouse.just_released(MouseButton::Right)
+        || !hook_mode.0
+    {
+        release_hook(&mut commands, player_entity, lines);
+        return;
+    }
+
+    player_vel.0 += to_anchor.normalize() * HOOK_PULL_ACCELERATION * time.delta_secs();
+}
+
+/// Let go of the hook, since the tile it was anchored to is gone along with the old world
+fn release_hook_in_new_world(
+    mut commands: Commands,
+    player: Single<Entity, (With<Player>, With<GrappleHook>)>,
+    lines: Query<Entity, With<HookLine>>,
+) {
+    release_hook(&mut commands, *player, lines);
+}
+
+/// Detach the hook from the player and get rid of its line
+fn release_hook(
+    commands: &mut Commands,
+    player_entity: Entity,
+    lines: Query<Entity, With<HookLine>>,
+) {
+    commands.entity(player_entity).remove::<GrappleHook>();
+    for line in lines {
+        commands.entity(line).despawn();
+    }
+}
+
+const HOOK_LINE_THICKNESS: f32 = 0.15;
+/// Stretch the hook line sprite between the player and the anchor
+fn draw_hook_line(
+    player: Single<(&Transform, &GrappleHook), (With<Player>, Without<HookLine>)>,
+    lines: Query<(&mut Transform, &mut Sprite), With<HookLine>>,
+) {
+    let (player_transform, hook) = player.into_inner();
+    let start = player_transform.translation.truncate();
+    let span = hook.anchor - start;
+
+    for (mut transform, mut sprite) in lines {
+        transform.translation = (start + span / 2.).extend(ZLayer::HookLine.z());
+        transform.rotation = Quat::from_rotation_z(span.to_angle());
+        sprite.custom_size = Some(Vec2::new(span.length(), HOOK_LINE_THICKNESS));
+    }
+}
@@ -1,9 +1,7 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{
-    player::Player,
-    ui::{TOOLBAR_BUTTONS, Toolbar, ToolbarSlotUpdate},
-};
+use crate::player::Player;
 
 pub struct InventoryPlugin;
 
@@ -11,59 +9,120 @@ impl Plugin for InventoryPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, (handle_item_pickups, handle_item_removals))
             .add_event::<ItemPickedUp>()
-            .add_event::<ItemRemoved>();
+            .add_event::<ItemRemoved>()
+            .add_event::<InventoryChanged>();
     }
 }
 
+/// Total number of inventory slots. The first `ToolbarSize` of these are shown on the toolbar and
+/// the rest only live in the inventory grid.
+pub const INVENTORY_SLOTS: usize = 20;
+
 #[derive(Component, Default)]
 /// Component to contain inventory information
 // This only needs to hold an array of block id's for now because the only interactable blocks are
 // the three types of foreground blocks, which are all stackable. This will change in the future
 // and require more complex inventory management.
 // Option should default to None which is perfect.
-pub struct Inventory(pub [Option<ItemStack>; 5]);
+pub struct Inventory(pub [Option<ItemStack>; INVENTORY_SLOTS]);
+
+impl Inventory {
+    /// Copy the inventory's contents into a plain snapshot
+    pub fn to_snapshot(&self) -> InventorySnapshot {
+        InventorySnapshot(
+            self.0
+                .iter()
+                .map(|slot| slot.map(|s| (s.item_id, s.count)))
+                .collect(),
+        )
+    }
+
+    /// Rebuild an inventory from a snapshot. Snapshot slots past the end of the inventory are
+    /// dropped and any slots the snapshot doesn't cover are left empty, as are slots saved with a
+    /// count of zero.
+    pub fn from_snapshot(snapshot: &InventorySnapshot) -> Self {
+        let mut inventory = Inventory::default();
+        for (slot, saved) in inventory.0.iter_mut().zip(&snapshot.0) {
+            *slot = saved
+                .filter(|&(_, count)| count > 0)
+                .map(|(item_id, count)| ItemStack::new(item_id, count));
+        }
+        inventory
+    }
+}
+
+/// Plain, serializable copy of an inventory's contents, one entry per slot holding the slot's
+/// `(item_id, count)` if it isn't empty. Keeps persistence decoupled from the Inventory component.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct InventorySnapshot(pub Vec<Option<(usize, usize)>>);
 
 // TODO: Not sure I want this to be totally public? Would have to move around the implementation
 // for the toolbar update or add functions somehow
+/// A stack of one kind of item. Slots hold None rather than an empty stack, so a stack in a slot
+/// always has at least one item.
 #[derive(Clone, Copy)]
 pub struct ItemStack {
     pub count: usize,
     pub item_id: usize,
 }
 
+/// Most items a single inventory slot can hold
+pub const MAX_STACK: usize = 9999;
+
+impl ItemStack {
+    pub fn new(item_id: usize, count: usize) -> Self {
+        ItemStack { count, item_id }
+    }
+
+    /// Add items to the stack up to MAX_STACK, returning how many didn't fit
+    pub fn add(&mut self, n: usize) -> usize {
+        let added = n.min(MAX_STACK.saturating_sub(self.count));
+        self.count += added;
+        n - added
+    }
+
+    /// Take items off the stack, returning what's left or None if that used the stack up. Taking
+    /// more than the stack holds just empties it.
+    pub fn remove(self, n: usize) -> Option<ItemStack> {
+        match self.count.checked_sub(n) {
+            Some(count) if count > 0 => Some(ItemStack::new(self.item_id, count)),
+            _ => None,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= MAX_STACK
+    }
+}
+
 #[derive(Event)]
 pub struct ItemPickedUp(pub usize);
 
 /// Process all pending ItemPickedUp events and modify the player's inventory accordingly
 fn handle_item_pickups(
     mut events: EventReader<ItemPickedUp>,
-    mut toolbar_events: EventWriter<ToolbarSlotUpdate>,
+    mut change_events: EventWriter<InventoryChanged>,
     mut inventory: Single<&mut Inventory, With<Player>>,
-    toolbar: Res<Toolbar>,
 ) {
     'event: for event in events.read() {
         let mut first_empty_slot: Option<usize> = None;
         // Iterate over all inventory slots
         for i in 0..(inventory.0.len()) {
-            match &inventory.0[i] {
-                // If the slot has a stack with matching item_id, put the item in this stack
-                Some(s) if s.item_id == event.0 => {
-                    inventory.0[i] = Some(ItemStack {
-                        item_id: s.item_id,
-                        count: s.count + 1,
+            match &mut inventory.0[i] {
+                // If the slot has a stack with matching item_id and room left, put the item in this
+                // stack
+                Some(s) if s.item_id == event.0 && !s.is_full() => {
+                    let before = Some(*s);
+                    s.add(1);
+                    change_events.write(InventoryChanged {
+                        slot: i,
+                        before,
+                        after: Some(*s),
                     });
-                    // Update toolbar
-                    if i >= TOOLBAR_BUTTONS {
-                        break 'event;
-                    } else if let Some(_) = toolbar.buttons.get(i) {
-                        toolbar_events.write(ToolbarSlotUpdate {
-                            stack: inventory.0[i],
-                            slot: i,
-                        });
-                    }
-                    break 'event;
+                    continue 'event;
                 }
-                // Track the first empty inventory slot we find, if any
+                // Track the first empty inventory slot we find, if any. Slots are scanned in
+                // order, so toolbar slots fill up before grid-only slots do.
                 None if first_empty_slot.is_none() => first_empty_slot = Some(i),
                 _ => {}
             }
@@ -71,23 +130,25 @@ fn handle_item_pickups(
 
         // If no such stack exists, put the item in the first empty slot
         if let Some(i) = first_empty_slot {
-            inventory.0[i] = Some(ItemStack {
-                item_id: event.0,
-                count: 1,
+            inventory.0[i] = Some(ItemStack::new(event.0, 1));
+            change_events.write(InventoryChanged {
+                slot: i,
+                before: None,
+                after: inventory.0[i],
             });
-            // Update toolbar
-            if i >= TOOLBAR_BUTTONS {
-                break;
-            } else if let Some(_) = toolbar.buttons.get(i) {
-                toolbar_events.write(ToolbarSlotUpdate {
-                    stack: inventory.0[i],
-                    slot: i,
-                });
-            }
         }
     }
 }
 
+/// Sent whenever the contents of an inventory slot change, so anything that shows or depends on
+/// the inventory can react without polling it
+#[derive(Event)]
+pub struct InventoryChanged {
+    pub slot: usize,
+    pub before: Option<ItemStack>,
+    pub after: Option<ItemStack>,
+}
+
 #[derive(Event)]
 pub struct ItemRemoved {
     pub slot: usize,
@@ -97,36 +158,112 @@ pub struct ItemRemoved {
 /// Handle removing items from the inventory
 fn handle_item_removals(
     mut events: EventReader<ItemRemoved>,
-    mut toolbar_events: EventWriter<ToolbarSlotUpdate>,
+    mut change_events: EventWriter<InventoryChanged>,
     mut inventory: Single<&mut Inventory, With<Player>>,
 ) {
     for e in events.read() {
         // Get the inventory item stack indicated by the event
         // This let-else just checks that the inventory slot actually exists, not whether or not
         // something is in it.
-        let Some(stack) = inventory.0.get(e.slot) else {
-            return;
+        let Some(&stack) = inventory.0.get(e.slot) else {
+            continue;
         };
 
-        // If there is an item stack, determine what the new slot state should be and take action
-        let new_stack = match stack {
-            // If there are enough items to subtract with at least 1 left, do so
-            Some(s) if s.count > e.amount => Some(ItemStack {
-                item_id: s.item_id,
-                count: s.count - e.amount,
-            }),
-            // If there isn't enough in the stack or no stack in the first place, return None
-            _ => None,
-        };
+        // Emptied stacks become None, so no slot is ever left holding zero items
+        let new_stack = stack.and_then(|s| s.remove(e.amount));
 
-        // Assign the stack data to the inventory slot, and write an event if the toolbar needs to
-        // be updated
+        // Assign the stack data to the inventory slot and let everything else know about it
         inventory.0[e.slot] = new_stack;
-        if e.slot < TOOLBAR_BUTTONS {
-            toolbar_events.write(ToolbarSlotUpdate {
-                stack: new_stack,
-                slot: e.slot,
-            });
+        change_events.write(InventoryChanged {
+            slot: e.slot,
+            before: stack,
+            after: new_stack,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an app with just the inventory systems and a player holding a starting inventory
+    fn inventory_app(inventory: Inventory) -> App {
+        let mut app = App::new();
+        app.add_plugins(InventoryPlugin);
+        app.world_mut().spawn((Player, inventory));
+        app
+    }
+
+    fn contents(app: &mut App) -> Vec<Option<(usize, usize)>> {
+        let mut inventories = app.world_mut().query::<&Inventory>();
+        inventories.single(app.world()).unwrap().to_snapshot().0
+    }
+
+    #[test]
+    fn every_pickup_in_a_frame_lands_with_a_full_slot() {
+        let mut inventory = Inventory::default();
+        inventory.0[0] = Some(ItemStack::new(1, MAX_STACK));
+        let mut app = inventory_app(inventory);
+
+        app.world_mut().send_event(ItemPickedUp(1));
+        app.world_mut().send_event(ItemPickedUp(1));
+        app.world_mut().send_event(ItemPickedUp(3));
+        app.update();
+
+        let contents = contents(&mut app);
+        assert_eq!(contents[0], Some((1, MAX_STACK)));
+        assert_eq!(contents[1], Some((1, 2)));
+        assert_eq!(contents[2], Some((3, 1)));
+    }
+
+    #[test]
+    fn removal_from_a_missing_slot_skips_only_that_event() {
+        let mut inventory = Inventory::default();
+        inventory.0[0] = Some(ItemStack::new(1, 5));
+        inventory.0[1] = Some(ItemStack::new(3, 1));
+        let mut app = inventory_app(inventory);
+
+        app.world_mut().send_event(ItemRemoved {
+            slot: INVENTORY_SLOTS,
+            amount: 1,
+        });
+        app.world_mut()
+            .send_event(ItemRemoved { slot: 0, amount: 2 });
+        app.world_mut()
+            .send_event(ItemRemoved { slot: 1, amount: 1 });
+        app.update();
+
+        let contents = contents(&mut app);
+        assert_eq!(contents[0], Some((1, 3)));
+        assert_eq!(contents[1], None);
+    }
+
+    #[test]
+    fn pickups_overflow_a_full_toolbar_into_the_grid() {
+        // The first five slots make up the toolbar by default
+        let mut inventory = Inventory::default();
+        inventory.0[0] = Some(ItemStack::new(1, MAX_STACK - 1));
+        for slot in 1..5 {
+            inventory.0[slot] = Some(ItemStack::new(2, MAX_STACK));
         }
+        let mut app = inventory_app(inventory);
+
+        app.world_mut().send_event(ItemPickedUp(1));
+        app.world_mut().send_event(ItemPickedUp(1));
+        app.world_mut().send_event(ItemPickedUp(1));
+        app.update();
+
+        let contents = contents(&mut app);
+        assert_eq!(contents[0], Some((1, MAX_STACK)));
+        assert_eq!(contents[5], Some((1, 2)));
+        assert!(contents[6..].iter().all(Option::is_none));
+
+        // Every change is reported against the slot it actually happened in
+        let events = app.world().resource::<Events<InventoryChanged>>();
+        let changed_slots: Vec<usize> = events
+            .iter_current_update_events()
+            .map(|e| e.slot)
+            .collect();
+        assert_eq!(changed_slots, [0, 5, 5]);
     }
 }
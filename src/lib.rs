@@ -1,29 +1,128 @@
 use assets::TileAssets;
 use avian2d::prelude::*;
-use bevy::prelude::*;
+use bevy::{input::InputPlugin, prelude::*, scene::ScenePlugin};
+use random::{GameRng, WorldSeed};
 
 mod assets;
+mod blocks;
 mod camera;
-mod inventory;
+mod hook;
+pub mod inventory;
+mod particles;
+mod platform;
 mod player;
+mod random;
+mod settings;
 mod terrain;
 mod ui;
 
-pub struct TerrustiaGamePlugin;
+pub use terrain::{GameMap, TileData, TileDestroyed};
+
+/// Draw order of everything in the world, back to front. Sprites take their z translation from
+/// here so nothing ends up hidden behind the terrain by accident.
+#[derive(Clone, Copy)]
+enum ZLayer {
+    /// Walls drawn behind tiles, and the sky where there are none
+    Background,
+    Tile,
+    TileHighlight,
+    Platform,
+    HookLine,
+    Player,
+    Particle,
+}
+
+impl ZLayer {
+    const fn z(self) -> f32 {
+        match self {
+            ZLayer::Background => -2.,
+            ZLayer::Tile => -1.,
+            ZLayer::TileHighlight => -0.5,
+            ZLayer::Platform => 0.,
+            ZLayer::HookLine => 0.5,
+            ZLayer::Player => 1.,
+            ZLayer::Particle => 1.5,
+        }
+    }
+}
+
+/// Which way gravity pulls. Grounding and jumping take "down" from here rather than assuming -Y,
+/// so flipping this turns the world upside down for the player.
+#[derive(Resource)]
+pub struct GravityDir(pub Dir2);
+
+const GRAVITY_STRENGTH: f32 = 50.;
+fn apply_gravity_dir(gravity_dir: Res<GravityDir>, mut gravity: ResMut<Gravity>) {
+    gravity.0 = gravity_dir.0 * GRAVITY_STRENGTH;
+}
+
+pub struct TerrustiaGamePlugin {
+    /// How many times per second FixedUpdate (and the physics simulation with it) runs. This can
+    /// still be changed while the game is running through `Time<Fixed>`.
+    pub fixed_update_hz: f64,
+    /// Run without a window or rendering, on top of only the handful of Bevy plugins the gameplay
+    /// needs. This is meant for driving the game from tests with `App::update`, where systems that
+    /// need a window or cursor simply don't run. Settings start at their defaults and are never
+    /// read from or written to disk.
+    pub headless: bool,
+}
+
+impl Default for TerrustiaGamePlugin {
+    fn default() -> Self {
+        TerrustiaGamePlugin {
+            fixed_update_hz: 64.,
+            headless: false,
+        }
+    }
+}
 
 impl Plugin for TerrustiaGamePlugin {
     fn build(&self, app: &mut App) {
+        match self.headless {
+            false => {
+                app.add_plugins(DefaultPlugins);
+            }
+            true => {
+                // Tile sprites still load images and the physics engine expects meshes and scenes
+                // to exist, so those asset types are registered even though nothing is ever drawn
+                app.add_plugins((
+                    MinimalPlugins,
+                    AssetPlugin::default(),
+                    ScenePlugin,
+                    InputPlugin,
+                    TransformPlugin,
+                ))
+                .init_asset::<Image>()
+                .init_asset::<Mesh>();
+            }
+        }
+
         app.add_plugins((
-            DefaultPlugins,
             PhysicsPlugins::default(),
+            // Loads saved settings, so it has to come before the plugins that use them
+            settings::SettingsPlugin {
+                persist: !self.headless,
+            },
             camera::CameraPlugin,
+            hook::GrapplingHookPlugin,
             inventory::InventoryPlugin,
+            particles::ParticlePlugin,
+            platform::MovingPlatformPlugin {
+                demo_platform: cfg!(debug_assertions) && !self.headless,
+            },
             player::CharacterControllerPlugin,
             terrain::TerrainPlugin,
             ui::UiPlugin,
         ))
+        .insert_resource(Time::<Fixed>::from_hz(self.fixed_update_hz))
         .insert_resource(ClearColor(Color::BLACK))
-        .insert_resource(Gravity(Vec2::NEG_Y * 50.))
-        .init_resource::<TileAssets>();
+        .insert_resource(GravityDir(Dir2::NEG_Y))
+        .add_systems(
+            PreUpdate,
+            apply_gravity_dir.run_if(resource_changed::<GravityDir>),
+        )
+        .init_resource::<TileAssets>()
+        .init_resource::<WorldSeed>()
+        .init_resource::<GameRng>();
     }
 }
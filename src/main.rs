@@ -2,5 +2,5 @@ use bevy::prelude::*;
 use terrustia::TerrustiaGamePlugin;
 
 fn main() {
-    App::new().add_plugins(TerrustiaGamePlugin).run();
+    App::new().add_plugins(TerrustiaGamePlugin::default()).run();
 }
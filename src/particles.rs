@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{ZLayer, random::GameRng};
+
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_particles);
+    }
+}
+
+/// A short lived sprite that drifts, falls and fades out before despawning
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    lifetime: Timer,
+}
+
+/// Downward acceleration on particles, much gentler than the world's gravity so they hang in the
+/// air for a moment
+const PARTICLE_GRAVITY: f32 = 15.;
+fn update_particles(
+    mut commands: Commands,
+    particles: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+    time: Res<Time>,
+) {
+    for (entity, mut particle, mut transform, mut sprite) in particles {
+        particle.lifetime.tick(time.delta());
+        if particle.lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        particle.velocity.y -= PARTICLE_GRAVITY * time.delta_secs();
+        transform.translation += (particle.velocity * time.delta_secs()).extend(0.);
+        sprite
+            .color
+            .set_alpha(particle.lifetime.fraction_remaining());
+    }
+}
+
+const DUST_SIZE: f32 = 0.25;
+const DUST_LIFETIME: f32 = 0.4;
+/// Fastest a dust particle can be kicked out sideways, in world units per second
+const DUST_SPEED: f32 = 4.;
+/// Spawn a puff of dust particles at a point, kicked up and out to either side
+pub fn spawn_dust(
+    commands: &mut Commands,
+    rng: &mut GameRng,
+    position: Vec2,
+    color: Color,
+    count: usize,
+) {
+    for _ in 0..count {
+        let velocity = Vec2::new(
+            rng.0.gen_range(-DUST_SPEED..=DUST_SPEED),
+            rng.0.gen_range(0.25..=0.75) * DUST_SPEED,
+        );
+        commands.spawn((
+            Particle {
+                velocity,
+                lifetime: Timer::from_seconds(DUST_LIFETIME, TimerMode::Once),
+            },
+            Sprite::from_color(color, Vec2::splat(DUST_SIZE)),
+            Transform::from_translation(position.extend(ZLayer::Particle.z())),
+        ));
+    }
+}
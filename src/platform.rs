@@ -0,0 +1,76 @@
+use avian2d::prelude::*;
+use bevy::{color::palettes::tailwind::STONE_700, prelude::*};
+
+use crate::ZLayer;
+
+pub struct MovingPlatformPlugin {
+    /// Spawn a platform near the spawn point to try riding on. The world doesn't generate any
+    /// platforms of its own yet.
+    pub demo_platform: bool,
+}
+
+impl Plugin for MovingPlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, move_platforms);
+        if self.demo_platform {
+            app.add_systems(Startup, spawn_demo_platform);
+        }
+    }
+}
+
+/// A body that travels back and forth along a path of points, looping back to the first point
+/// after reaching the last one
+#[derive(Component)]
+#[require(RigidBody = RigidBody::Kinematic)]
+pub struct MovingPlatform {
+    pub path: Vec<Vec2>,
+    pub speed: f32,
+    /// Index of the path point the platform is currently heading toward
+    target: usize,
+}
+
+impl MovingPlatform {
+    pub fn new(path: Vec<Vec2>, speed: f32) -> Self {
+        MovingPlatform {
+            path,
+            speed,
+            target: 0,
+        }
+    }
+}
+
+/// Steer each platform toward its next path point. Platforms are kinematic, so setting their
+/// velocity is enough for the physics engine to move them and push whatever is in the way.
+fn move_platforms(
+    platforms: Query<(&mut MovingPlatform, &Transform, &mut LinearVelocity)>,
+    time: Res<Time>,
+) {
+    for (mut platform, transform, mut velocity) in platforms {
+        let Some(&target) = platform.path.get(platform.target) else {
+            velocity.0 = Vec2::ZERO;
+            continue;
+        };
+
+        // Move on to the next point once this one is close enough to reach this frame
+        let position = transform.translation.truncate();
+        let mut to_target = target - position;
+        if to_target.length() <= platform.speed * time.delta_secs() {
+            platform.target = (platform.target + 1) % platform.path.len();
+            to_target = platform.path[platform.target] - position;
+        }
+
+        velocity.0 = to_target.normalize_or_zero() * platform.speed;
+    }
+}
+
+const PLATFORM_SIZE: Vec2 = Vec2::new(4., 0.5);
+/// Spawn a platform that shuttles back and forth a little above the surface near the spawn point
+fn spawn_demo_platform(mut commands: Commands) {
+    let start = Vec2::new(10., 5.);
+    commands.spawn((
+        MovingPlatform::new(vec![start, Vec2::new(24., 5.)], 4.),
+        Collider::rectangle(PLATFORM_SIZE.x, PLATFORM_SIZE.y),
+        Sprite::from_color(STONE_700, PLATFORM_SIZE),
+        Transform::from_translation(start.extend(ZLayer::Platform.z())),
+    ));
+}
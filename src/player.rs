@@ -1,13 +1,46 @@
 use avian2d::{math::Vector, prelude::*};
 use bevy::prelude::*;
 
-use crate::inventory::Inventory;
+use crate::{
+    GravityDir, ZLayer,
+    blocks::block,
+    camera::{CameraShake, flying},
+    inventory::Inventory,
+    particles::spawn_dust,
+    platform::MovingPlatform,
+    random::GameRng,
+    settings::AccessibilitySettings,
+    terrain::{SurfaceHeights, WorldGenerated, WorldTiles},
+};
 
 pub struct CharacterControllerPlugin;
 
 impl Plugin for CharacterControllerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, ((update_grounded, keyboard_movement).chain(),))
+        app.init_resource::<HomePoint>()
+            .add_systems(
+                Update,
+                (
+                    (
+                        update_grounded,
+                        // Reads the fall speed before landing resets it
+                        kick_up_dust,
+                        shake_on_hard_landing,
+                        // The movement keys steer the fly camera instead while it's active.
+                        // Crouching and stepping up work in terms of rows above the feet, so they
+                        // only make sense while gravity points straight down.
+                        crouch.run_if(not(flying).and(gravity_is_down)),
+                        ride_platforms,
+                        step_up.run_if(not(flying).and(gravity_is_down)),
+                        keyboard_movement.run_if(not(flying)),
+                    )
+                        .chain(),
+                    orient_ground_caster.run_if(resource_changed::<GravityDir>),
+                    place_player_on_surface,
+                    forget_home_point.run_if(on_event::<WorldGenerated>),
+                    (set_home_point, teleport_home),
+                ),
+            )
             .add_systems(Startup, spawn_player);
     }
 }
@@ -28,14 +61,18 @@ struct Grounded;
 /// tolerance.
 const HIT_TOLERANCE_RADIANS: f32 = 0.1;
 /// Update the Grounded state of the player using its shape caster
-fn update_grounded(player: Single<(Entity, &ShapeHits), With<Player>>, mut commands: Commands) {
+fn update_grounded(
+    player: Single<(Entity, &ShapeHits), With<Player>>,
+    gravity_dir: Res<GravityDir>,
+    mut commands: Commands,
+) {
     let (player_entity, caster_hits) = player.into_inner();
 
     // Iterate over every collision occuring with the Player. If there is a collision with normal
-    // facing upward, the player is grounded
+    // facing against gravity, the player is grounded
     if caster_hits
         .iter()
-        .any(|hit| -hit.normal2.angle_to(Vector::Y).abs() < HIT_TOLERANCE_RADIANS)
+        .any(|hit| is_ground_hit(hit, gravity_dir.0))
     {
         commands.entity(player_entity).insert(Grounded);
     } else {
@@ -43,56 +80,519 @@ fn update_grounded(player: Single<(Entity, &ShapeHits), With<Player>>, mut comma
     }
 }
 
+/// Check whether a ground caster hit is close enough to flat to be standing on, given which way
+/// gravity pulls
+fn is_ground_hit(hit: &ShapeHitData, down: Dir2) -> bool {
+    hit.normal2.angle_to(-*down).abs() < HIT_TOLERANCE_RADIANS
+}
+
+/// Run condition for whether gravity points straight down, as the world is normally laid out
+fn gravity_is_down(gravity_dir: Res<GravityDir>) -> bool {
+    gravity_dir.0 == Dir2::NEG_Y
+}
+
+/// Point the player's ground caster along gravity, so "ground" is wherever gravity pulls them
+fn orient_ground_caster(
+    mut caster: Single<&mut ShapeCaster, With<Player>>,
+    gravity_dir: Res<GravityDir>,
+) {
+    caster.direction = gravity_dir.0;
+}
+
+/// Set how fast the player is moving against gravity, leaving their motion across it alone
+fn set_upward_speed(velocity: &mut Vec2, down: Dir2, speed: f32) {
+    *velocity -= *down * velocity.dot(*down);
+    *velocity -= *down * speed;
+}
+
+/// Downward speed the player had the last time they were in the air, used to tell how hard they
+/// landed
+#[derive(Component, Default)]
+struct FallSpeed(f32);
+
+/// Landing faster than this shakes the camera, harder the faster the landing
+const HARD_LANDING_SPEED: f32 = 30.;
+fn shake_on_hard_landing(
+    player: Single<(&LinearVelocity, &mut FallSpeed, Has<Grounded>), With<Player>>,
+    mut shake: ResMut<CameraShake>,
+    gravity_dir: Res<GravityDir>,
+) {
+    let (player_vel, mut fall_speed, player_grounded) = player.into_inner();
+
+    // The collision has already stopped the player by the time they're grounded, so remember how
+    // fast they were falling while still in the air
+    if !player_grounded {
+        fall_speed.0 = player_vel.dot(*gravity_dir.0).max(0.);
+        return;
+    }
+
+    if fall_speed.0 > HARD_LANDING_SPEED {
+        shake.add_trauma((fall_speed.0 - HARD_LANDING_SPEED) / HARD_LANDING_SPEED);
+    }
+    fall_speed.0 = 0.;
+}
+
+/// Horizontal speed below which the player counts as standing still
+const STATIONARY_SPEED: f32 = 0.5;
+/// Landing slower than this (e.g. dropping off a single tile) doesn't kick up any dust
+const DUST_LANDING_SPEED: f32 = 8.;
+const LANDING_DUST_COUNT: usize = 10;
+const FOOTSTEP_DUST_COUNT: usize = 3;
+/// Kick up dust from the block under the player's feet when they land or start running along the
+/// ground
+fn kick_up_dust(
+    player: Single<
+        (
+            &Transform,
+            &Sprite,
+            &LinearVelocity,
+            &FallSpeed,
+            Has<Grounded>,
+        ),
+        With<Player>,
+    >,
+    world_tiles: WorldTiles,
+    mut was_moving: Local<bool>,
+    mut commands: Commands,
+    mut rng: ResMut<GameRng>,
+    accessibility: Res<AccessibilitySettings>,
+) {
+    let (transform, sprite, player_vel, fall_speed, player_grounded) = player.into_inner();
+
+    let moving = player_grounded && player_vel.x.abs() > STATIONARY_SPEED;
+    let started_moving = moving && !*was_moving;
+    *was_moving = moving;
+
+    let count = if player_grounded && fall_speed.0 > DUST_LANDING_SPEED {
+        LANDING_DUST_COUNT
+    } else if started_moving {
+        FOOTSTEP_DUST_COUNT
+    } else {
+        return;
+    };
+
+    // Sample just below the bottom of the sprite, which follows the player's height when crouching
+    let height = sprite.custom_size.map_or(PLAYER_HEIGHT, |size| size.y);
+    let feet = transform.translation.truncate() - Vec2::Y * height / 2.;
+    let Some(block_id) = world_tiles.solid_block_under(&(feet - Vec2::Y * 0.1)) else {
+        return;
+    };
+    spawn_dust(
+        &mut commands,
+        &mut rng,
+        feet,
+        Color::from(block(block_id).display_color(accessibility.colorblind_palette)),
+        count,
+    );
+}
+
+/// Horizontal velocity the player has picked up from the moving platform they're standing on
+#[derive(Component, Default)]
+struct PlatformCarry(f32);
+
+/// Carry the player along with any moving platform they're standing on. Stepping off keeps the
+/// velocity the platform gave them.
+fn ride_platforms(
+    player: Single<(&ShapeHits, &mut LinearVelocity, &mut PlatformCarry), With<Player>>,
+    platforms: Query<&LinearVelocity, (With<MovingPlatform>, Without<Player>)>,
+    gravity_dir: Res<GravityDir>,
+) {
+    let (caster_hits, mut player_vel, mut carry) = player.into_inner();
+    let platform_vel = caster_hits
+        .iter()
+        .filter(|hit| is_ground_hit(hit, gravity_dir.0))
+        .find_map(|hit| platforms.get(hit.entity).ok());
+
+    // Only apply the change in the platform's velocity so the player's own movement on top of it
+    // is kept. Vertical motion is left to the collision, which already pushes the player up.
+    match platform_vel {
+        Some(platform_vel) => {
+            player_vel.x += platform_vel.x - carry.0;
+            carry.0 = platform_vel.x;
+        }
+        None => carry.0 = 0.,
+    }
+}
+
+/// Mark a player that is crouching, which shrinks them to fit through low gaps
+#[derive(Component)]
+pub struct Crouching;
+
+/// Height of the player while crouched. Small enough to fit through a two tile high gap.
+pub const CROUCH_HEIGHT: f32 = 1.9;
+/// Crouch while S is held, and stand back up once it's released and there's room overhead
+fn crouch(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    player: Single<(Entity, &mut Transform, &mut Sprite, Has<Crouching>), With<Player>>,
+    world_tiles: WorldTiles,
+) {
+    let (player_entity, mut transform, mut sprite, crouching) = player.into_inner();
+    let wants_crouch = keyboard.pressed(KeyCode::KeyS);
+    let height_difference = PLAYER_HEIGHT - CROUCH_HEIGHT;
+
+    // Resizing happens around the player's center, so shift them to keep their feet in place
+    let (new_height, shift) = if wants_crouch && !crouching {
+        commands.entity(player_entity).insert(Crouching);
+        (CROUCH_HEIGHT, -height_difference / 2.)
+    } else if !wants_crouch && crouching {
+        // Standing up is blocked by any solid tile in the space the player's head would fill
+        let crouched_top = transform.translation.y + CROUCH_HEIGHT / 2.;
+        let head_room = Rect::new(
+            transform.translation.x - PLAYER_WIDTH / 2.,
+            crouched_top,
+            transform.translation.x + PLAYER_WIDTH / 2.,
+            crouched_top + height_difference,
+        );
+        if world_tiles.any_solid_in(head_room) {
+            return;
+        }
+        commands.entity(player_entity).remove::<Crouching>();
+        (PLAYER_HEIGHT, height_difference / 2.)
+    } else {
+        return;
+    };
+
+    transform.translation.y += shift;
+    sprite.custom_size = Some(Vec2::new(PLAYER_WIDTH, new_height));
+    commands
+        .entity(player_entity)
+        .insert((player_collider(new_height), ground_caster(new_height)));
+}
+
+/// Tallest ledge the player will step up onto without jumping, in world units
+const MAX_STEP_HEIGHT: f32 = 1.;
+/// Extra height the step up hop clears the ledge by, so the player doesn't catch its corner
+const STEP_CLEARANCE: f32 = 0.1;
+/// Hop the player up onto low ledges they walk into, so a single tile step doesn't stop them dead
+fn step_up(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    gravity: Res<Gravity>,
+    player: Single<
+        (
+            &Transform,
+            &mut LinearVelocity,
+            Has<Grounded>,
+            Has<Crouching>,
+        ),
+        With<Player>,
+    >,
+    world_tiles: WorldTiles,
+) {
+    let (transform, mut player_vel, player_grounded, player_crouching) = player.into_inner();
+    let left = keyboard.pressed(KeyCode::KeyA) as i8;
+    let right = keyboard.pressed(KeyCode::KeyD) as i8;
+    let direction = f32::from(right - left);
+    if !player_grounded || direction == 0. {
+        return;
+    }
+
+    let height = match player_crouching {
+        true => CROUCH_HEIGHT,
+        false => PLAYER_HEIGHT,
+    };
+    let position = transform.translation.truncate();
+    let feet = position.y - height / 2.;
+
+    // Look at the column just past the player's leading edge. A tile at row y covers y - 1 to y,
+    // so the first row above the feet is the one that would block them.
+    let front_x = position.x + direction * (PLAYER_WIDTH / 2. + 0.1);
+    let column = front_x.floor() as i16;
+    let first_row = (feet + 0.01).ceil() as i16;
+    let solid_at = |row| {
+        world_tiles
+            .get((column, row))
+            .is_some_and(|tile| tile.solid)
+    };
+    if !solid_at(first_row) {
+        return;
+    }
+
+    // Find the top of the obstacle, and give up if it's too tall to step onto
+    let mut top_row = first_row;
+    while solid_at(top_row + 1) {
+        top_row += 1;
+    }
+    let step_height = f32::from(top_row) - feet;
+    if step_height > MAX_STEP_HEIGHT {
+        return;
+    }
+
+    // The player needs room to stand on top of the step, both where they are and over the step
+    let standing_room = Rect::new(
+        position.x.min(front_x) - PLAYER_WIDTH / 2.,
+        f32::from(top_row) + 0.01,
+        position.x.max(front_x) + PLAYER_WIDTH / 2.,
+        f32::from(top_row) + height,
+    );
+    if world_tiles.any_solid_in(standing_room) {
+        return;
+    }
+
+    // Launch just fast enough to clear the step
+    let hop_vel = (2. * -gravity.0.y * (step_height + STEP_CLEARANCE)).sqrt();
+    player_vel.y = player_vel.y.max(hop_vel);
+}
+
+/// Seconds left on a jump pressed in midair, which fires as soon as the player lands
+#[derive(Component, Default)]
+struct JumpBuffer(f32);
+
+/// Jumps the player can make while airborne, refilled whenever they touch the ground
+#[derive(Component, Default)]
+pub struct AirJumps {
+    /// How many air jumps are allowed per trip off the ground. Starts at 0 and can be raised to
+    /// unlock double (or triple, ...) jumping.
+    pub allowed: u8,
+    remaining: u8,
+}
+
 const HORIZONTAL_VELOCITY_MAX: f32 = 20.;
 const HORIZONTAL_ACCELERATION: f32 = 10.;
+/// How quickly the player slows down on the ground once they stop pressing a direction
+const GROUND_DECELERATION: f32 = 30.;
+/// Fraction of the normal horizontal acceleration available while crouching
+const CROUCH_ACCELERATION_FACTOR: f32 = 0.5;
 const JUMP_VEL: f32 = 20.;
+/// How long before landing a jump press still counts, in seconds
+const JUMP_BUFFER_TIME: f32 = 0.12;
 /// Check for input every frame
 fn keyboard_movement(
     keyboard: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
-    player: Single<(&mut LinearVelocity, Has<Grounded>), With<Player>>,
+    player: Single<
+        (
+            &mut LinearVelocity,
+            &mut AirJumps,
+            &mut JumpBuffer,
+            &PlatformCarry,
+            Has<Grounded>,
+            Has<Crouching>,
+        ),
+        With<Player>,
+    >,
+    gravity_dir: Res<GravityDir>,
 ) {
-    let (mut player_vel, player_grounded) = player.into_inner();
+    let (mut player_vel, mut air_jumps, mut jump_buffer, carry, player_grounded, player_crouching) =
+        player.into_inner();
 
     // Get horizontal direction from A/D
     let left = keyboard.pressed(KeyCode::KeyA) as i8;
     let right = keyboard.pressed(KeyCode::KeyD) as i8;
-    // Accelerate horizontal velocity
-    player_vel.x += HORIZONTAL_ACCELERATION * f32::from(right - left) * time.delta_secs();
+    // Accelerate horizontal velocity, more slowly while crouched
+    let acceleration = match player_crouching {
+        true => HORIZONTAL_ACCELERATION * CROUCH_ACCELERATION_FACTOR,
+        false => HORIZONTAL_ACCELERATION,
+    };
+    player_vel.x += acceleration * f32::from(right - left) * time.delta_secs();
+
+    // Without horizontal input on the ground, brake to a stop (relative to any platform being
+    // ridden) instead of coasting on the light damping that keeps momentum in the air
+    if player_grounded && left == right {
+        let relative_vel = player_vel.x - carry.0;
+        let braking = (GROUND_DECELERATION * time.delta_secs()).min(relative_vel.abs());
+        player_vel.x -= braking * relative_vel.signum();
+    }
 
-    // If W / Space is pressed and the player is grounded, set their velocity to a fixed value
+    jump_buffer.0 = (jump_buffer.0 - time.delta_secs()).max(0.);
+
+    // If W / Space is pressed (or was pressed just before landing) and the player is grounded, set
+    // their velocity to a fixed value. Touching the ground also refills air jumps.
     if player_grounded {
-        if keyboard.any_pressed([KeyCode::KeyW, KeyCode::Space]) {
-            player_vel.y = JUMP_VEL;
+        air_jumps.remaining = air_jumps.allowed;
+        if keyboard.any_pressed([KeyCode::KeyW, KeyCode::Space]) || jump_buffer.0 > 0. {
+            jump_buffer.0 = 0.;
+            set_upward_speed(&mut player_vel.0, gravity_dir.0, JUMP_VEL);
         }
+        return;
+    }
+
+    // Air jumps need a fresh press so holding jump off a ledge doesn't burn them immediately
+    if !keyboard.any_just_pressed([KeyCode::KeyW, KeyCode::Space]) {
+        return;
+    }
+    if air_jumps.remaining > 0 {
+        air_jumps.remaining -= 1;
+        set_upward_speed(&mut player_vel.0, gravity_dir.0, JUMP_VEL);
+    } else {
+        // Out of jumps, so hold onto the press in case the player is about to land
+        jump_buffer.0 = JUMP_BUFFER_TIME;
     }
 }
 
+// The player's size is defined only here. The sprite, collider and ground caster are all derived
+// from it, so changing these is enough to resize the player.
 pub const PLAYER_WIDTH: f32 = 2.;
 pub const PLAYER_HEIGHT: f32 = 3.;
+/// How much smaller the collider is than the player's sprite along each axis
+const COLLIDER_INSET: f32 = 0.1;
+/// How much smaller the ground caster is than the player's sprite along each axis. This has to be
+/// less than COLLIDER_INSET so the caster pokes out past the collider and touches the ground.
+const GROUND_CASTER_INSET: f32 = 0.03;
+const _: () = assert!(GROUND_CASTER_INSET < COLLIDER_INSET);
 fn spawn_player(mut commands: Commands) {
     commands.spawn((
         Player,
         RigidBody::Dynamic,
-        Collider::rectangle(PLAYER_WIDTH - 0.1, PLAYER_HEIGHT - 0.1),
+        player_collider(PLAYER_HEIGHT),
         Sprite {
             color: Color::from(Srgba::new(1., 1., 1., 1.)),
             custom_size: Some(Vec2::new(PLAYER_WIDTH, PLAYER_HEIGHT)),
             ..default()
         },
-        Transform::from_xyz(0., 30., 1.),
-        // A ShapeCaster to help detect if the player is touching the ground.
-        ShapeCaster::new(
-            Collider::rectangle(PLAYER_WIDTH * 0.99, PLAYER_HEIGHT * 0.99),
-            Vector::ZERO,
-            0.,
-            Dir2::NEG_Y,
-        )
-        .with_max_distance(0.1),
+        Transform::from_xyz(0., 30., ZLayer::Player.z()),
+        ground_caster(PLAYER_HEIGHT),
         LockedAxes::ROTATION_LOCKED,
         Friction::new(0.1).with_combine_rule(CoefficientCombine::Min),
         CollisionMargin(0.05),
         LinearDamping(0.1),
+        AirJumps::default(),
+        JumpBuffer::default(),
+        PlatformCarry::default(),
+        FallSpeed::default(),
         Inventory::default(),
     ));
 }
+
+/// Build the player's collider for a given body height
+pub fn player_collider(height: f32) -> Collider {
+    Collider::rectangle(PLAYER_WIDTH - COLLIDER_INSET, height - COLLIDER_INSET)
+}
+
+/// Build a ShapeCaster to help detect if the player is touching the ground, sized to a given body
+/// height
+fn ground_caster(height: f32) -> ShapeCaster {
+    ShapeCaster::new(
+        Collider::rectangle(
+            PLAYER_WIDTH - GROUND_CASTER_INSET,
+            height - GROUND_CASTER_INSET,
+        ),
+        Vector::ZERO,
+        0.,
+        Dir2::NEG_Y,
+    )
+    .with_max_distance(0.1)
+}
+
+/// Column the player spawns in once the world has been generated
+const SPAWN_COLUMN: i16 = 0;
+
+/// Where the player has chosen to return to, if anywhere
+#[derive(Resource, Default)]
+struct HomePoint(Option<Vec2>);
+
+/// Set the home point to where the player is standing when H is pressed
+fn set_home_point(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    player: Single<(&Transform, Has<Grounded>), With<Player>>,
+    mut home: ResMut<HomePoint>,
+) {
+    let (transform, player_grounded) = player.into_inner();
+    // Setting it mid-jump would leave the player falling every time they come home
+    if keyboard.just_pressed(KeyCode::KeyH) && player_grounded {
+        home.0 = Some(transform.translation.truncate());
+    }
+}
+
+/// Teleport the player to their home point when T is pressed. If something has been built there
+/// since, they're put on the surface of that column instead, and if that's not possible either
+/// they stay put.
+fn teleport_home(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    player: Single<(&mut Transform, &mut LinearVelocity), With<Player>>,
+    home: Res<HomePoint>,
+    world_tiles: WorldTiles,
+    surface_heights: Res<SurfaceHeights>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    let Some(home) = home.0 else {
+        return;
+    };
+
+    // The player sinks into the ground by a hair while standing, so the room they need is shrunk
+    // a little to keep the ground under a saved spot from counting as in the way
+    let fits = |center: Vec2| {
+        !world_tiles.any_solid_in(Rect::from_center_size(
+            center,
+            Vec2::new(PLAYER_WIDTH, PLAYER_HEIGHT) - 0.02,
+        ))
+    };
+    let destination = match fits(home) {
+        true => home,
+        false => {
+            // A tile at row y spans from y - 1 up to y in world space, so the surface's top is at y
+            let column = home.x.floor() as i16;
+            let Some(surface) = surface_heights.surface_height(column) else {
+                return;
+            };
+            let on_surface = Vec2::new(home.x, f32::from(surface) + PLAYER_HEIGHT / 2.);
+            if !fits(on_surface) {
+                return;
+            }
+            on_surface
+        }
+    };
+
+    let (mut transform, mut player_vel) = player.into_inner();
+    transform.translation = destination.extend(transform.translation.z);
+    player_vel.0 = Vec2::ZERO;
+}
+
+/// Forget the home point once the world it was set in has been replaced
+fn forget_home_point(mut home: ResMut<HomePoint>) {
+    home.0 = None;
+}
+
+/// Move the player so they stand on the surface of a freshly generated world
+fn place_player_on_surface(
+    mut events: EventReader<WorldGenerated>,
+    player: Single<(&mut Transform, &mut LinearVelocity), With<Player>>,
+) {
+    let (mut transform, mut player_vel) = player.into_inner();
+    for event in events.read() {
+        let Some(&surface) = event.surface_heights.get(&SPAWN_COLUMN) else {
+            continue;
+        };
+
+        // A tile at row y spans from y - 1 up to y in world space, so the surface's top is at y
+        transform.translation.x = f32::from(SPAWN_COLUMN) + 0.5;
+        transform.translation.y = f32::from(surface) + PLAYER_HEIGHT / 2.;
+        // Don't carry a fall from the old world into the new one
+        player_vel.0 = Vec2::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit_with_normal(normal: Vector) -> ShapeHitData {
+        ShapeHitData {
+            entity: Entity::PLACEHOLDER,
+            distance: 0.,
+            point1: Vector::ZERO,
+            point2: Vector::ZERO,
+            normal1: -normal,
+            normal2: normal,
+        }
+    }
+
+    #[test]
+    fn floor_is_ground() {
+        assert!(is_ground_hit(&hit_with_normal(Vector::Y), Dir2::NEG_Y));
+    }
+
+    #[test]
+    fn wall_is_not_ground() {
+        assert!(!is_ground_hit(&hit_with_normal(Vector::X), Dir2::NEG_Y));
+        assert!(!is_ground_hit(&hit_with_normal(Vector::NEG_X), Dir2::NEG_Y));
+    }
+
+    #[test]
+    fn ground_follows_gravity() {
+        assert!(is_ground_hit(&hit_with_normal(Vector::X), Dir2::NEG_X));
+        assert!(!is_ground_hit(&hit_with_normal(Vector::Y), Dir2::NEG_X));
+    }
+}
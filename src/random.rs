@@ -0,0 +1,24 @@
+use bevy::prelude::*;
+use rand::{SeedableRng, rngs::StdRng};
+
+/// Seed that all of a world's randomness derives from
+#[derive(Resource)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        WorldSeed(rand::random())
+    }
+}
+
+/// Shared source of randomness for gameplay systems. Drawing from this rather than the thread
+/// local RNG means the same WorldSeed always plays out the same way.
+#[derive(Resource)]
+pub struct GameRng(pub StdRng);
+
+impl FromWorld for GameRng {
+    fn from_world(world: &mut World) -> Self {
+        let seed = world.get_resource_or_init::<WorldSeed>().0;
+        GameRng(StdRng::seed_from_u64(seed))
+    }
+}
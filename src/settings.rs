@@ -0,0 +1,240 @@
+use std::{fs, io, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::CameraSettings, ui::HudSettings};
+
+pub struct SettingsPlugin {
+    /// Load the settings from the settings file and save them back whenever they change. Without
+    /// this every setting starts at its default and the file is never touched.
+    pub persist: bool,
+}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        // Insert the settings before the other plugins get the chance to init defaults
+        let settings = match self.persist {
+            true => Settings::load(Path::new(SETTINGS_PATH)),
+            false => Settings::default(),
+        };
+        app.insert_resource(settings.camera)
+            .insert_resource(settings.accessibility)
+            .insert_resource(settings.hud);
+        if self.persist {
+            app.add_systems(Update, save_settings);
+        }
+    }
+}
+
+/// Player-facing options that change how the game is presented
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+    /// Show tile break progress as a bright overlay instead of darkening the tile
+    pub high_contrast: bool,
+    /// Use block colors that stay distinguishable with red-green color blindness
+    pub colorblind_palette: bool,
+}
+
+/// File the settings are kept in, relative to the working directory
+const SETTINGS_PATH: &str = "settings.ron";
+
+/// Most decimal places the coordinate readout can be set to show
+const MAX_COORDINATE_PRECISION: usize = 6;
+
+/// Every setting that's saved to disk, gathered up so they can be written and read as one file.
+/// Each group still lives in its own resource while the game is running.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub camera: CameraSettings,
+    pub accessibility: AccessibilitySettings,
+    pub hud: HudSettings,
+}
+
+impl Settings {
+    /// Read settings from a file. A missing file just means nothing has been saved yet, and a file
+    /// that can't be read or parsed is reported and ignored, so this always returns usable settings.
+    /// Values that parse but make no sense are replaced too, see sanitize.
+    pub fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Settings::default(),
+            Err(e) => {
+                warn!(
+                    "Couldn't read settings from {}, using defaults: {e}",
+                    path.display()
+                );
+                return Settings::default();
+            }
+        };
+
+        let mut settings: Settings = ron::from_str(&contents).unwrap_or_else(|e| {
+            warn!(
+                "Couldn't parse settings in {}, using defaults: {e}",
+                path.display()
+            );
+            Settings::default()
+        });
+        settings.sanitize();
+        settings
+    }
+
+    /// Fix up values that would misbehave or panic once the game uses them, warning about each one.
+    /// Numbers that aren't finite or are out of range go back to their defaults, and a zoom range
+    /// written backwards is turned around.
+    fn sanitize(&mut self) {
+        let defaults = CameraSettings::default();
+        let camera = &mut self.camera;
+        // Each value with its default and whether it also has to be above zero
+        let values = [
+            (
+                "catch_up_time",
+                &mut camera.catch_up_time,
+                defaults.catch_up_time,
+                true,
+            ),
+            (
+                "zoom_speed",
+                &mut camera.zoom_speed,
+                defaults.zoom_speed,
+                false,
+            ),
+            ("zoom_min", &mut camera.zoom_min, defaults.zoom_min, true),
+            ("zoom_max", &mut camera.zoom_max, defaults.zoom_max, true),
+            (
+                "lookahead_per_speed",
+                &mut camera.lookahead_per_speed,
+                defaults.lookahead_per_speed,
+                false,
+            ),
+            (
+                "lookahead_max",
+                &mut camera.lookahead_max,
+                defaults.lookahead_max,
+                false,
+            ),
+            (
+                "fly_speed",
+                &mut camera.fly_speed,
+                defaults.fly_speed,
+                false,
+            ),
+        ];
+        for (name, value, default, positive) in values {
+            let in_range = match positive {
+                true => *value > 0.,
+                false => *value >= 0.,
+            };
+            if !value.is_finite() || !in_range {
+                warn!("Camera setting {name} can't be {value}, using {default} instead");
+                *value = default;
+            }
+        }
+        if camera.zoom_min > camera.zoom_max {
+            warn!("Camera zoom_min is larger than zoom_max, swapping them");
+            std::mem::swap(&mut camera.zoom_min, &mut camera.zoom_max);
+        }
+
+        if self.hud.coordinate_precision > MAX_COORDINATE_PRECISION {
+            warn!(
+                "Coordinate precision can't be more than {MAX_COORDINATE_PRECISION}, using that instead"
+            );
+            self.hud.coordinate_precision = MAX_COORDINATE_PRECISION;
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(io::Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+/// Write the settings back to disk whenever any of them change
+fn save_settings(
+    camera: Res<CameraSettings>,
+    accessibility: Res<AccessibilitySettings>,
+    hud: Res<HudSettings>,
+) {
+    // Being inserted at startup counts as a change, but there's nothing new to save then
+    let changed = (camera.is_changed() && !camera.is_added())
+        || (accessibility.is_changed() && !accessibility.is_added())
+        || (hud.is_changed() && !hud.is_added());
+    if !changed {
+        return;
+    }
+
+    let settings = Settings {
+        camera: camera.clone(),
+        accessibility: accessibility.clone(),
+        hud: hud.clone(),
+    };
+    if let Err(e) = settings.save(Path::new(SETTINGS_PATH)) {
+        warn!("Couldn't save settings to {SETTINGS_PATH}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Path in the temp directory for a test to keep a settings file at
+    fn temp_settings_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("terrustia-{name}-{}.ron", std::process::id()))
+    }
+
+    #[test]
+    fn saved_settings_load_back() {
+        let path = temp_settings_path("round-trip");
+        let mut settings = Settings::default();
+        settings.camera.zoom_max = 0.3;
+        settings.accessibility.colorblind_palette = true;
+        settings.hud.tile_coordinates = true;
+        settings.save(&path).unwrap();
+
+        let loaded = Settings::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.camera.zoom_max, 0.3);
+        assert!(loaded.accessibility.colorblind_palette);
+        assert!(loaded.hud.tile_coordinates);
+    }
+
+    #[test]
+    fn nonsense_values_load_as_usable_settings() {
+        let path = temp_settings_path("nonsense");
+        fs::write(
+            &path,
+            "(camera: (zoom_min: 0.3, zoom_max: 0.1, lookahead_max: -2.0, catch_up_time: NaN), \
+             hud: (coordinate_precision: 100000))",
+        )
+        .unwrap();
+
+        let loaded = Settings::load(&path);
+        fs::remove_file(&path).unwrap();
+        let camera = &loaded.camera;
+        assert_eq!((camera.zoom_min, camera.zoom_max), (0.1, 0.3));
+        assert_eq!(
+            camera.lookahead_max,
+            CameraSettings::default().lookahead_max
+        );
+        assert_eq!(
+            camera.catch_up_time,
+            CameraSettings::default().catch_up_time
+        );
+        assert_eq!(loaded.hud.coordinate_precision, MAX_COORDINATE_PRECISION);
+    }
+
+    #[test]
+    fn unparseable_file_loads_defaults() {
+        let path = temp_settings_path("unparseable");
+        fs::write(&path, "(camera: (zoom_min: ").unwrap();
+
+        let loaded = Settings::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.camera.zoom_min, CameraSettings::default().zoom_min);
+    }
+}
@@ -1,20 +1,30 @@
-use avian2d::prelude::{Collider, RigidBody};
+use std::{collections::VecDeque, time::Duration};
+
+use avian2d::prelude::{AnyCollider, Collider, LinearVelocity, RigidBody, Rotation};
 use bevy::{
-    color::palettes::tailwind::{
-        AMBER_700, AMBER_900, CYAN_400, GREEN_700, NEUTRAL_950, STONE_500, STONE_700,
-    },
-    platform::collections::HashMap,
+    color::palettes::tailwind::{CYAN_400, SKY_800, YELLOW_300},
+    ecs::system::SystemParam,
+    input::InputSystem,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     time::Stopwatch,
     window::PrimaryWindow,
 };
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use round_to::{CeilTo, FloorTo};
 
 use crate::{
+    ZLayer,
     assets::TileAssets,
+    blocks::{HAND_TIER, block},
+    camera::CameraShake,
+    hook::HookMode,
     inventory::{Inventory, ItemPickedUp, ItemRemoved},
+    particles::spawn_dust,
     player::Player,
-    ui::Toolbar,
+    random::{GameRng, WorldSeed},
+    settings::AccessibilitySettings,
+    ui::{Toolbar, UiPointer},
 };
 
 pub struct TerrainPlugin;
@@ -22,11 +32,48 @@ pub struct TerrainPlugin;
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<GameMap>()
+            .init_resource::<TileEdits>()
+            .init_resource::<CreativeMode>()
+            .init_resource::<GridOverlay>()
+            .init_resource::<SurfaceHeights>()
+            .init_resource::<SkyGradient>()
+            .init_resource::<LastPlacement>()
+            .add_event::<WorldGenerated>()
+            .add_event::<RegenerateWorld>()
+            .add_event::<Explosion>()
             .add_observer(tile_destruction)
             .add_observer(tile_placement)
-            .add_systems(Startup, build_terrain)
-            .add_systems(FixedUpdate, tile_interaction)
-            .add_systems(Update, (tile_sprite_updates, tile_breaking_effect));
+            .add_systems(
+                Startup,
+                (build_terrain, spawn_map_boundaries, spawn_tile_highlight),
+            )
+            // Rebuilding despawns every tile, so it happens before anything else this frame gets the
+            // chance to queue commands on the old ones
+            .add_systems(
+                PreUpdate,
+                (regenerate_on_key, regenerate_world)
+                    .chain()
+                    .after(InputSystem),
+            )
+            .add_systems(FixedUpdate, (tile_interaction, decay_break_timers).chain())
+            .add_systems(
+                Update,
+                (
+                    tile_sprite_updates,
+                    update_tile_palette.run_if(resource_changed::<AccessibilitySettings>),
+                    update_sky_gradient.run_if(resource_changed::<SkyGradient>),
+                    tile_breaking_effect,
+                    reset_break_effect,
+                    tile_highlight,
+                    sync_tile_colliders,
+                    update_surface_heights,
+                    undo_last,
+                    toggle_creative_mode,
+                    toggle_grid_overlay,
+                    draw_tile_grid.run_if(resource_equals(GridOverlay(true))),
+                    (creative_explosions, handle_explosions).chain(),
+                ),
+            );
     }
 }
 
@@ -35,26 +82,144 @@ impl Plugin for TerrainPlugin {
 pub struct GameMap(HashMap<(i16, i16), Entity>);
 
 impl GameMap {
+    /// Return the tile at a map coordinate
+    pub fn tile_at(&self, coord: (i16, i16)) -> Option<Entity> {
+        self.0.get(&coord).copied()
+    }
+
     /// Return the tile under a certain position in world space
     pub fn tile_under(&self, world_space: &Vec2) -> Option<Entity> {
-        match self
-            .0
-            .get(&(world_space.x.floor_to(), world_space.y.ceil_to()))
-        {
-            Some(&e) => Some(e.to_owned()),
-            None => None,
-        }
+        self.tile_at(tile_coord(world_space))
+    }
+}
+
+// Map coordinates name a tile by the world space position of its top-left corner, so the tile at
+// (x, y) covers x..x + 1 horizontally and y - 1..y vertically. tile_center and tile_coord are the
+// only places that convert a single point between the two, and everything else should go through
+// them so the tile a click lands on is always the tile drawn under the cursor.
+//
+// Tiles are exactly one world unit across. Tile sprites and colliders are unit squares centered on
+// tile_center, and code that works on whole areas of the grid (any_solid_in, tiles_along_line, the
+// grid overlay, map_world_rect, the player's step up) counts tile boundaries in whole units.
+
+/// Return the collider for a solid tile, covering exactly the tile's cell
+fn tile_collider() -> Collider {
+    Collider::rectangle(1., 1.)
+}
+
+/// Return the world space position of the center of the tile at a map coordinate
+pub fn tile_center((x, y): (i16, i16)) -> Vec2 {
+    Vec2::new(f32::from(x) + 0.5, f32::from(y) - 0.5)
+}
+
+/// Return the map coordinate of the tile covering a position in world space
+pub fn tile_coord(world_space: &Vec2) -> (i16, i16) {
+    (world_space.x.floor_to(), world_space.y.ceil_to())
+}
+
+/// System parameter to read tile data by map coordinate without wiring up a GameMap lookup and a
+/// TileData query by hand
+#[derive(SystemParam)]
+pub struct WorldTiles<'w, 's> {
+    game_map: Res<'w, GameMap>,
+    tiles: Query<'w, 's, &'static TileData>,
+}
+
+impl WorldTiles<'_, '_> {
+    /// Return the data of the tile at a map coordinate
+    pub fn get(&self, coord: (i16, i16)) -> Option<TileData> {
+        self.game_map
+            .tile_at(coord)
+            .and_then(|e| self.tiles.get(e).ok())
+            .copied()
+    }
+
+    /// Return the data of the tile under a certain position in world space
+    pub fn get_under(&self, world_space: &Vec2) -> Option<TileData> {
+        self.game_map
+            .tile_under(world_space)
+            .and_then(|e| self.tiles.get(e).ok())
+            .copied()
+    }
+
+    /// Return the id of the solid block under a certain position in world space, if there is one
+    pub fn solid_block_under(&self, world_space: &Vec2) -> Option<usize> {
+        self.get_under(world_space)
+            .filter(|tile| tile.solid)
+            .map(|tile| tile.fg_id)
+    }
+
+    /// Walk along a ray in world space and return the first point that lands in a solid tile
+    // Sampling at a fixed step can clip the very corner of a tile, but the step is small enough
+    // compared to a tile that it doesn't matter in practice
+    pub fn raycast_solid(&self, origin: Vec2, direction: Dir2, max_distance: f32) -> Option<Vec2> {
+        let steps = (max_distance / RAYCAST_STEP) as usize;
+        (0..=steps)
+            .map(|i| origin + direction * (i as f32 * RAYCAST_STEP))
+            .find(|point| self.get_under(point).is_some_and(|tile| tile.solid))
+    }
+
+    /// Check whether any solid tile overlaps a rectangle in world space
+    pub fn any_solid_in(&self, rect: Rect) -> bool {
+        // Tile (x, y) covers world space from x to x + 1 horizontally and y - 1 to y vertically
+        let mut columns = (rect.min.x.floor() as i16)..(rect.max.x.ceil() as i16);
+        let rows = (rect.min.y.floor() as i16 + 1)..=(rect.max.y.ceil() as i16);
+        columns.any(|x| {
+            rows.clone()
+                .any(|y| self.get((x, y)).is_some_and(|tile| tile.solid))
+        })
     }
 }
 
+/// Distance between samples taken by WorldTiles::raycast_solid
+const RAYCAST_STEP: f32 = 0.1;
+
 /// Contain the stateful data within a tile
-#[derive(Component, Clone, Copy)]
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
 pub struct TileData {
     fg_id: usize, // Foreground tile id
     bg_id: usize, // Background tile id
     solid: bool,  // Should entities collide with the tile?
 }
 
+impl TileData {
+    /// Id of the block in the tile's foreground, or 0 if it's open
+    pub fn fg_id(&self) -> usize {
+        self.fg_id
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.solid
+    }
+
+    /// This tile with its foreground block taken out, leaving the background behind
+    fn emptied(self) -> Self {
+        TileData {
+            fg_id: 0,
+            solid: false,
+            ..self
+        }
+    }
+
+    /// This tile with a solid block put in its foreground
+    fn with_block(self, fg_id: usize) -> Self {
+        TileData {
+            fg_id,
+            solid: true,
+            ..self
+        }
+    }
+}
+
+/// Change a tile's data, returning what it was before. Every change to a tile goes through here.
+/// Colliders, sprites and surface heights are all kept up to date from TileData changes, so the
+/// data is the only thing to write, and the tile is only marked changed if it really is different.
+fn set_tile(tile: &mut Mut<TileData>, new_data: TileData) -> TileData {
+    let before = **tile;
+    tile.set_if_neq(new_data);
+    before
+}
+
 impl Default for TileData {
     fn default() -> Self {
         TileData {
@@ -69,14 +234,86 @@ impl Default for TileData {
 // that information for a short time - Maybe I should keep it for up to X tiles (e.g. 3-4?)
 /// Component to help keep track of tile(s) currently being destroyed
 #[derive(Component, Default)]
-struct BreakTimer(Stopwatch);
+struct BreakTimer {
+    /// How long the tile has been broken for in seconds, scaled by the held tool's break rate. This
+    /// is a plain number rather than a Stopwatch so break state is easy to inspect and snapshot.
+    progress: f32,
+    /// How long it's been since the player last worked on this tile
+    idle: Stopwatch,
+}
+
+impl BreakTimer {
+    fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    fn advance(&mut self, step: Duration) {
+        self.progress += step.as_secs_f32();
+    }
+}
+
+/// Sent once the map has been built and all of its tiles spawned
+#[derive(Event)]
+pub struct WorldGenerated {
+    /// Bounds of the map in tile coordinates, inclusive on all sides
+    pub bounds: IRect,
+    /// Tile y coordinate of the highest solid tile in each column, keyed by column. This is the
+    /// surface as generated, and SurfaceHeights keeps following it as tiles change.
+    pub surface_heights: HashMap<i16, i16>,
+}
+
+/// Tile y coordinate of the highest solid tile in each column, keyed by column. Kept up to date as
+/// tiles are broken and placed.
+#[derive(Resource, Default)]
+pub struct SurfaceHeights(HashMap<i16, i16>);
+
+impl SurfaceHeights {
+    /// Return the y coordinate of the surface tile in a column, or None if the column has no solid
+    /// tiles at all
+    pub fn surface_height(&self, x: i16) -> Option<i16> {
+        self.0.get(&x).copied()
+    }
+}
+
+/// Move column surfaces up when a tile is placed above them, and down when the surface tile itself
+/// is removed
+fn update_surface_heights(
+    changed_tiles: Query<(&TileData, &Transform), Changed<TileData>>,
+    world_tiles: WorldTiles,
+    mut surface_heights: ResMut<SurfaceHeights>,
+) {
+    for (tile, transform) in changed_tiles {
+        let (x, y) = tile_coord(&transform.translation.truncate());
+        let surface = surface_heights.surface_height(x);
+
+        if tile.solid && surface.is_none_or(|surface| y > surface) {
+            surface_heights.0.insert(x, y);
+        } else if !tile.solid && surface == Some(y) {
+            // Look down the column for the next solid tile to become the new surface
+            match (BOTTOM_EDGE..y)
+                .rev()
+                .find(|&below| world_tiles.get((x, below)).is_some_and(|t| t.solid))
+            {
+                Some(new_surface) => surface_heights.0.insert(x, new_surface),
+                None => surface_heights.0.remove(&x),
+            };
+        }
+    }
+}
 
+/// Trigger on a tile entity to work on breaking it, the same as holding the left mouse button on
+/// it for one FixedUpdate tick
 #[derive(Event)]
-struct TileDestroyed;
+pub struct TileDestroyed;
 
 #[derive(Event)]
 struct TilePlaced;
 
+/// Tile a block was last placed in during the current right click drag, so holding the button
+/// over it doesn't keep trying to place there
+#[derive(Resource, Default)]
+struct LastPlacement(Option<(i16, i16)>);
+
 /// Detect and trigger events on tiles by mouse input
 fn tile_interaction(
     mut commands: Commands,
@@ -84,140 +321,1020 @@ fn tile_interaction(
     mouse: Res<ButtonInput<MouseButton>>,
     window: Single<&Window, With<PrimaryWindow>>,
     game_map: Res<GameMap>,
+    hook_mode: Res<HookMode>,
+    ui_pointer: UiPointer,
+    mut last_placement: ResMut<LastPlacement>,
+    mut drag_cursor: Local<Option<(i16, i16)>>,
 ) {
-    // Tile interaction can only occur when one of the mouse buttons is pressed
-    if !mouse.any_pressed([MouseButton::Left, MouseButton::Right]) {
+    // Letting go of right click ends a placement drag
+    if !mouse.pressed(MouseButton::Right) {
+        last_placement.0 = None;
+    }
+
+    // Tile interaction can only occur when one of the mouse buttons is pressed, and not when the
+    // click is on the UI
+    if !mouse.any_pressed([MouseButton::Left, MouseButton::Right]) || ui_pointer.over_ui() {
+        *drag_cursor = None;
         return;
     }
 
-    // Get the mouse position and convert to world space coordinates
-    let cursor_pos = window.cursor_position().unwrap();
-    let world_pos = camera.0.viewport_to_world_2d(camera.1, cursor_pos).unwrap();
+    // Get the mouse position and convert to world space coordinates. Buttons stay held while the
+    // cursor is dragged outside the window, and there's nothing under it to interact with then.
+    let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|p| camera.0.viewport_to_world_2d(camera.1, p).ok())
+    else {
+        *drag_cursor = None;
+        return;
+    };
+    let coord = tile_coord(&world_pos);
 
-    // Trigger Tile observers on the tile occupying those coordinates
-    if let Some(t) = game_map.tile_under(&world_pos) {
-        for button in mouse.get_pressed() {
-            match button {
-                // Entities implement Clone since they wrap an identifier for the ECS (like a key)
-                MouseButton::Left => commands.trigger_targets(TileDestroyed, t),
-                MouseButton::Right => commands.trigger_targets(TilePlaced, t),
-                _ => continue,
-            }
+    // Trigger Tile observers on the tile occupying those coordinates. Entities implement Clone
+    // since they wrap an identifier for the ECS (like a key).
+    if mouse.pressed(MouseButton::Left)
+        && let Some(t) = game_map.tile_at(coord)
+    {
+        commands.trigger_targets(TileDestroyed, t);
+    }
+
+    // Right click fires the grappling hook instead while in hook mode
+    if !mouse.pressed(MouseButton::Right) || hook_mode.0 {
+        *drag_cursor = None;
+        return;
+    }
+
+    // A fast drag can cross several tiles between ticks, so place along the whole path the cursor
+    // took rather than only where it ended up. Each tile is tried until a block goes in.
+    let path: Vec<(i16, i16)> = match drag_cursor.replace(coord) {
+        Some(previous) if previous != coord => {
+            tiles_along_line(tile_center(previous), tile_center(coord)).collect()
+        }
+        _ => vec![coord],
+    };
+    for coord in path {
+        if last_placement.0 == Some(coord) {
+            continue;
+        }
+        if let Some(t) = game_map.tile_at(coord) {
+            commands.trigger_targets(TilePlaced, t);
         }
     }
 }
 
+/// Marker component for the overlay that tints the tile under the cursor
+#[derive(Component)]
+struct TileHighlight;
+
+fn spawn_tile_highlight(mut commands: Commands) {
+    commands.spawn((
+        TileHighlight,
+        Sprite::from_color(HIGHLIGHT_VALID_COLOR, Vec2::ONE),
+        Transform::from_xyz(0., 0., ZLayer::TileHighlight.z()),
+        Visibility::Hidden,
+    ));
+}
+
+const HIGHLIGHT_VALID_COLOR: Srgba = Srgba::new(1., 1., 1., 0.25);
+const HIGHLIGHT_INVALID_COLOR: Srgba = Srgba::new(1., 0.2, 0.2, 0.25);
+/// Move the highlight overlay onto the tile under the cursor, tinted by whether clicking on that
+/// tile would actually do anything
+fn tile_highlight(
+    highlight: Single<(&mut Transform, &mut Sprite, &mut Visibility), With<TileHighlight>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    world_tiles: WorldTiles,
+    player: Single<(&Inventory, &Transform), (With<Player>, Without<TileHighlight>)>,
+    toolbar: Res<Toolbar>,
+    creative: Res<CreativeMode>,
+    ui_pointer: UiPointer,
+) {
+    let (mut transform, mut sprite, mut visibility) = highlight.into_inner();
+    let (inventory, player_transform) = player.into_inner();
+
+    // Nothing in the world can be clicked through the UI, so don't highlight anything behind it
+    if ui_pointer.over_ui() {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+
+    // Find the tile under the cursor, hiding the highlight if the cursor is outside the window or
+    // off the edge of the map
+    let Some(coord) = window
+        .cursor_position()
+        .and_then(|p| camera.0.viewport_to_world_2d(camera.1, p).ok())
+        .map(|world_pos| tile_coord(&world_pos))
+    else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    let Some(tile) = world_tiles.get(coord) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    // Solid tiles can be broken if the held tool is strong enough and the player can see them (or
+    // always in creative mode), open tiles can have the held item placed in them if there's
+    // something for it to attach to
+    let solid_at = |c| world_tiles.get(c).is_some_and(|t| t.solid);
+    let valid = match tile.solid {
+        true => {
+            creative.0
+                || (break_rate(inventory, &toolbar, tile.fg_id).is_some()
+                    && line_of_sight(player_transform.translation.truncate(), coord, solid_at))
+        }
+        false => {
+            matches!(inventory.0.get(toolbar.selected), Some(Some(_)))
+                && (creative.0 || has_support(coord, &tile, solid_at))
+        }
+    };
+
+    *visibility = Visibility::Visible;
+    transform.translation = tile_center(coord).extend(transform.translation.z);
+    sprite.color = Color::from(match valid {
+        true => HIGHLIGHT_VALID_COLOR,
+        false => HIGHLIGHT_INVALID_COLOR,
+    });
+}
+
+/// Whether tiles break instantly without dropping anything and placing blocks is free, for building
+/// and testing
+#[derive(Resource, Default)]
+pub struct CreativeMode(pub bool);
+
+fn toggle_creative_mode(keyboard: Res<ButtonInput<KeyCode>>, mut creative: ResMut<CreativeMode>) {
+    if keyboard.just_pressed(KeyCode::KeyC) {
+        creative.0 = !creative.0;
+    }
+}
+
+/// Whether tile boundaries are drawn over the world, to help with precise building
+#[derive(Resource, Default, PartialEq)]
+pub struct GridOverlay(pub bool);
+
+fn toggle_grid_overlay(keyboard: Res<ButtonInput<KeyCode>>, mut grid: ResMut<GridOverlay>) {
+    if keyboard.just_pressed(KeyCode::KeyB) {
+        grid.0 = !grid.0;
+    }
+}
+
+/// Grid line opacity when zoomed in closely enough for the lines to be fully drawn
+const GRID_ALPHA: f32 = 0.2;
+/// Projection scales between which the grid fades out as the camera zooms out, since the lines
+/// bunch up into noise once tiles are only a few pixels wide
+const GRID_FADE_START_SCALE: f32 = 0.1;
+const GRID_FADE_END_SCALE: f32 = 0.3;
+/// Draw the boundaries of the tiles currently in view
+fn draw_tile_grid(mut gizmos: Gizmos, camera: Single<(&Camera, &GlobalTransform, &Projection)>) {
+    let (camera, camera_transform, projection) = camera.into_inner();
+    let Projection::Orthographic(ortho_projection) = projection else {
+        return;
+    };
+
+    let fade = ((ortho_projection.scale - GRID_FADE_START_SCALE)
+        / (GRID_FADE_END_SCALE - GRID_FADE_START_SCALE))
+        .clamp(0., 1.);
+    if fade >= 1. {
+        return;
+    }
+    let color = Color::srgba(1., 1., 1., GRID_ALPHA * (1. - fade));
+
+    // Only lines within both the view and the map are drawn
+    let Some(viewport) = camera.logical_viewport_rect() else {
+        return;
+    };
+    let (Ok(corner_a), Ok(corner_b)) = (
+        camera.viewport_to_world_2d(camera_transform, viewport.min),
+        camera.viewport_to_world_2d(camera_transform, viewport.max),
+    ) else {
+        return;
+    };
+    let visible = Rect::from_corners(corner_a, corner_b).intersect(map_world_rect());
+    if visible.is_empty() {
+        return;
+    }
+
+    // Tile boundaries fall on whole numbers along both axes
+    for x in (visible.min.x.ceil() as i32)..=(visible.max.x.floor() as i32) {
+        let x = x as f32;
+        gizmos.line_2d(
+            Vec2::new(x, visible.min.y),
+            Vec2::new(x, visible.max.y),
+            color,
+        );
+    }
+    for y in (visible.min.y.ceil() as i32)..=(visible.max.y.floor() as i32) {
+        let y = y as f32;
+        gizmos.line_2d(
+            Vec2::new(visible.min.x, y),
+            Vec2::new(visible.max.x, y),
+            color,
+        );
+    }
+}
+
 const BREAK_TIME: f32 = 0.6;
+/// Fraction of the normal break speed when the held tool is one tier below what the block needs
+const UNDER_TIER_BREAK_RATE: f32 = 0.25;
 /// Modify tiles according to what happens in the world. Player must hold the left mouse button
 /// down over a period of time before the tile will actually break.
 fn tile_destruction(
     trigger: Trigger<TileDestroyed>,
-    mut tiles: Query<(&mut TileData, Option<&mut BreakTimer>)>,
+    mut tiles: Query<(&mut TileData, &Transform, Option<&mut BreakTimer>)>,
     mut commands: Commands,
     time_fixed: Res<Time<Fixed>>,
     mut item_events: EventWriter<ItemPickedUp>,
+    player: Single<(&Inventory, &Transform), With<Player>>,
+    toolbar: Res<Toolbar>,
+    mut rng: ResMut<GameRng>,
+    mut edits: ResMut<TileEdits>,
+    creative: Res<CreativeMode>,
+    game_map: Res<GameMap>,
 ) {
-    let (mut tile, break_timer) = tiles.get_mut(trigger.target()).unwrap();
+    let (inventory, player_transform) = player.into_inner();
+
+    // Tiles hidden behind other solid tiles can't be reached, except in creative mode
+    let coord = tile_coord(
+        &tiles
+            .get(trigger.target())
+            .unwrap()
+            .1
+            .translation
+            .truncate(),
+    );
+    let solid_at = |c| {
+        game_map
+            .tile_at(c)
+            .and_then(|e| tiles.get(e).ok())
+            .is_some_and(|(t, ..)| t.solid)
+    };
+    if !creative.0 && !line_of_sight(player_transform.translation.truncate(), coord, solid_at) {
+        return;
+    }
+
+    let (mut tile, tile_transform, break_timer) = tiles.get_mut(trigger.target()).unwrap();
 
     // Tiles that aren't solid can't be broken
     if !tile.solid {
         return;
     }
 
-    // Add a new timer to this tile if it's not already in the process of being broken
-    // tile_interaction runs on FixedUpdate so use Time<Fixed> to advance stopwatches.
-    // This observer will run at some arbitrary time after FixedUpdate, so use the
-    // timestep() to advance rather than delta()
-    let Some(mut break_timer) = break_timer else {
-        let mut new_timer = BreakTimer::default();
-        new_timer.0.tick(time_fixed.timestep());
-        commands.entity(trigger.target()).insert(new_timer);
-        return;
-    };
+    // Creative mode breaks anything instantly, so the break timer only matters outside of it
+    if !creative.0 {
+        // Tiles the held tool is too weak for can't be broken at all
+        let Some(break_rate) = break_rate(inventory, &toolbar, tile.fg_id) else {
+            return;
+        };
+        let break_step = time_fixed.timestep().mul_f32(break_rate);
 
-    // Tick this tile's timer, but if it isn't ready yet don't destroy it
-    break_timer.0.tick(time_fixed.timestep());
-    if break_timer.0.elapsed_secs() < BREAK_TIME {
-        return;
+        // Add a new timer to this tile if it's not already in the process of being broken
+        // tile_interaction runs on FixedUpdate so use Time<Fixed> to advance the timer.
+        // This observer will run at some arbitrary time after FixedUpdate, so use the
+        // timestep() to advance rather than delta()
+        let Some(mut break_timer) = break_timer else {
+            let mut new_timer = BreakTimer::default();
+            new_timer.advance(break_step);
+            commands.entity(trigger.target()).insert(new_timer);
+            return;
+        };
+
+        // Tick this tile's timer, but if it isn't ready yet don't destroy it
+        break_timer.advance(break_step);
+        break_timer.idle.reset();
+        if break_timer.progress() < BREAK_TIME {
+            return;
+        }
     }
 
-    // Send the item to the player's inventory
-    item_events.write(ItemPickedUp(tile.fg_id));
+    // Roll the block's drop table and send the results to the player's inventory. Creative mode
+    // doesn't drop anything.
+    let items_gained = match creative.0 {
+        true => Vec::new(),
+        false => roll_drops(tile.fg_id, &mut rng, &mut item_events),
+    };
 
     // Modify the TileData and remove the BreakTimer component
     commands.entity(trigger.target()).remove::<BreakTimer>();
-    tile.fg_id = 0;
-    tile.solid = false;
-    // Remove the tile's collider if present
-    commands.entity(trigger.target()).remove::<Collider>();
+    let emptied = tile.emptied();
+    let before = set_tile(&mut tile, emptied);
+
+    edits.record(TileEdit {
+        coord: tile_coord(&tile_transform.translation.truncate()),
+        before,
+        after: *tile,
+        item_spent: None,
+        items_gained,
+    });
+}
+
+/// Roll a block's drop table and send the results to the player's inventory, returning the item id
+/// of every item dropped
+fn roll_drops(
+    fg_id: usize,
+    rng: &mut GameRng,
+    item_events: &mut EventWriter<ItemPickedUp>,
+) -> Vec<usize> {
+    let mut items = Vec::new();
+    for drop in block(fg_id).drops {
+        if !rng.0.gen_bool(drop.chance) {
+            continue;
+        }
+        for _ in 0..rng.0.gen_range(drop.min_count..=drop.max_count) {
+            item_events.write(ItemPickedUp(drop.item_id));
+            items.push(drop.item_id);
+        }
+    }
+    items
+}
+
+/// Return how fast the player can break a block with the item they're holding, relative to the
+/// normal speed, or None if they can't break it at all
+fn break_rate(inventory: &Inventory, toolbar: &Toolbar, fg_id: usize) -> Option<f32> {
+    // The item in the selected toolbar slot acts as the mining tool
+    let held_tier = match inventory.0.get(toolbar.selected) {
+        Some(Some(stack)) => block(stack.item_id).tool_tier,
+        _ => HAND_TIER,
+    };
+
+    tier_break_rate(block(fg_id).required_tier, held_tier)
+}
+
+/// Return how fast a tool of the held tier breaks a block that requires a certain tier, relative
+/// to the normal speed, or None if it can't break it at all
+fn tier_break_rate(required_tier: u8, held_tier: u8) -> Option<f32> {
+    // Tools one tier short of the block's requirement break it slowly, anything weaker than that
+    // makes no progress at all
+    match required_tier.saturating_sub(held_tier) {
+        0 => Some(1.),
+        1 => Some(UNDER_TIER_BREAK_RATE),
+        _ => None,
+    }
 }
 
 fn tile_placement(
     trigger: Trigger<TilePlaced>,
-    mut tiles: Query<&mut TileData>,
-    mut commands: Commands,
-    inventory: Single<&Inventory, With<Player>>,
+    mut tiles: Query<(&mut TileData, &Transform)>,
+    player: Single<(&Inventory, &Transform, &Collider), With<Player>>,
     toolbar: Res<Toolbar>,
     mut item_events: EventWriter<ItemRemoved>,
+    mut edits: ResMut<TileEdits>,
+    creative: Res<CreativeMode>,
+    game_map: Res<GameMap>,
+    mut last_placement: ResMut<LastPlacement>,
 ) {
-    let mut tile = tiles.get_mut(trigger.target()).unwrap();
+    let (inventory, player_transform, player_collider) = player.into_inner();
+    let (tile, tile_transform) = tiles.get(trigger.target()).unwrap();
+    let coord = tile_coord(&tile_transform.translation.truncate());
 
     // Solid objects can't be placed on top of other solid objects
     if tile.solid {
         return;
     }
 
+    // Blocks need something to attach to, except in creative mode
+    let solid_at = |c| {
+        game_map
+            .tile_at(c)
+            .and_then(|e| tiles.get(e).ok())
+            .is_some_and(|(t, _)| t.solid)
+    };
+    if !creative.0 && !has_support(coord, tile, solid_at) {
+        return;
+    }
+
+    // Solid objects also can't be placed inside the player, which would wedge them in the block
+    if overlaps_player(coord, player_transform, player_collider) {
+        return;
+    }
+
     // Get the item stack currently in hand
     let Some(stack) = inventory.0.get(toolbar.selected).unwrap() else {
         return;
     };
 
-    tile.fg_id = stack.item_id;
-    tile.solid = true;
-    item_events.write(ItemRemoved {
-        slot: toolbar.selected,
-        amount: 1,
+    let mut tile = tiles.get_mut(trigger.target()).unwrap().0;
+    let placed = tile.with_block(stack.item_id);
+    let before = set_tile(&mut tile, placed);
+
+    // Creative mode places blocks for free
+    let item_spent = match creative.0 {
+        true => None,
+        false => {
+            item_events.write(ItemRemoved {
+                slot: toolbar.selected,
+                amount: 1,
+            });
+            Some(stack.item_id)
+        }
+    };
+
+    edits.record(TileEdit {
+        coord,
+        before,
+        after: *tile,
+        item_spent,
+        items_gained: Vec::new(),
+    });
+    last_placement.0 = Some(coord);
+}
+
+/// Check whether the tile at a map coordinate overlaps the player's body. This goes by the
+/// player's collider as it is right now, so a crouching player leaves room above them.
+fn overlaps_player(
+    coord: (i16, i16),
+    player_transform: &Transform,
+    player_collider: &Collider,
+) -> bool {
+    let tile_rect = Rect::from_center_size(tile_center(coord), Vec2::ONE);
+    let player_aabb =
+        player_collider.aabb(player_transform.translation.truncate(), Rotation::default());
+    let player_rect = Rect::from_corners(player_aabb.min, player_aabb.max);
+    !tile_rect.intersect(player_rect).is_empty()
+}
+
+/// How many of the player's most recent tile edits can be undone
+const UNDO_HISTORY: usize = 32;
+
+/// A change the player made to a tile, along with the items it moved in or out of their inventory
+struct TileEdit {
+    coord: (i16, i16),
+    before: TileData,
+    after: TileData,
+    /// Item taken from the inventory to make the edit, e.g. the block that was placed
+    item_spent: Option<usize>,
+    /// Items the edit added to the inventory, e.g. the drops from a broken block
+    items_gained: Vec<usize>,
+}
+
+/// Recent tile edits, oldest first, kept so they can be undone
+#[derive(Resource, Default)]
+struct TileEdits(VecDeque<TileEdit>);
+
+impl TileEdits {
+    /// Remember an edit, forgetting the oldest one if the history is full
+    fn record(&mut self, edit: TileEdit) {
+        if self.0.len() == UNDO_HISTORY {
+            self.0.pop_front();
+        }
+        self.0.push_back(edit);
+    }
+}
+
+/// Undo the most recent tile edit when Ctrl+Z is pressed, handing back the item it used up or
+/// taking back the items it gave
+fn undo_last(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut edits: ResMut<TileEdits>,
+    game_map: Res<GameMap>,
+    mut tiles: Query<&mut TileData>,
+    player: Single<(&Inventory, &Transform, &Collider), With<Player>>,
+    mut pickup_events: EventWriter<ItemPickedUp>,
+    mut removal_events: EventWriter<ItemRemoved>,
+) {
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !ctrl || !keyboard.just_pressed(KeyCode::KeyZ) {
+        return;
+    }
+    let Some(edit) = edits.0.pop_back() else {
+        return;
+    };
+    let Some(mut tile) = game_map
+        .tile_at(edit.coord)
+        .and_then(|entity| tiles.get_mut(entity).ok())
+    else {
+        return;
+    };
+    let (inventory, player_transform, player_collider) = player.into_inner();
+
+    // If the tile has changed since the edit, undoing it would clobber that newer change
+    if *tile != edit.after {
+        return;
+    }
+
+    // Restoring a solid tile inside the player would wedge them in it, so keep the edit around to
+    // try again once they've moved
+    if edit.before.solid && overlaps_player(edit.coord, player_transform, player_collider) {
+        edits.0.push_back(edit);
+        return;
+    }
+
+    set_tile(&mut tile, edit.before);
+
+    if let Some(item_id) = edit.item_spent {
+        pickup_events.write(ItemPickedUp(item_id));
+    }
+
+    // Take back whatever the edit gave the player, as far as they still have it
+    let mut gained: HashMap<usize, usize> = HashMap::new();
+    for &item_id in &edit.items_gained {
+        *gained.entry(item_id).or_default() += 1;
+    }
+    for (item_id, amount) in gained {
+        let Some(slot) = inventory
+            .0
+            .iter()
+            .position(|slot| matches!(slot, Some(stack) if stack.item_id == item_id))
+        else {
+            continue;
+        };
+        removal_events.write(ItemRemoved { slot, amount });
+    }
+}
+
+/// Blast a circle of tiles out of the map and throw nearby bodies away from the center
+#[derive(Event)]
+pub struct Explosion {
+    pub center: Vec2,
+    pub radius: f32,
+    /// Blocks needing a tool tier below this are destroyed, anything tougher survives. This also
+    /// scales the knockback and camera shake.
+    pub power: f32,
+}
+
+/// Bodies are pushed by an explosion out to this many times its radius
+const KNOCKBACK_RANGE_FACTOR: f32 = 2.;
+/// Speed given to a body at the center of an explosion, per point of power
+const KNOCKBACK_SPEED_PER_POWER: f32 = 15.;
+/// Camera trauma added per point of explosion power
+const TRAUMA_PER_POWER: f32 = 0.25;
+/// Debris particles thrown out of each tile an explosion destroys
+const DEBRIS_PER_TILE: usize = 3;
+fn handle_explosions(
+    mut events: EventReader<Explosion>,
+    game_map: Res<GameMap>,
+    mut tiles: Query<&mut TileData>,
+    mut bodies: Query<(&Transform, &RigidBody, &mut LinearVelocity)>,
+    mut commands: Commands,
+    mut rng: ResMut<GameRng>,
+    mut item_events: EventWriter<ItemPickedUp>,
+    mut shake: ResMut<CameraShake>,
+    creative: Res<CreativeMode>,
+    accessibility: Res<AccessibilitySettings>,
+) {
+    for explosion in events.read() {
+        // Check every tile whose center is inside the blast
+        let (min_x, max_y) =
+            tile_coord(&(explosion.center + Vec2::new(-1., 1.) * explosion.radius));
+        let (max_x, min_y) =
+            tile_coord(&(explosion.center + Vec2::new(1., -1.) * explosion.radius));
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let coord = (x, y);
+                if tile_center(coord).distance(explosion.center) > explosion.radius {
+                    continue;
+                }
+                let Some(entity) = game_map.tile_at(coord) else {
+                    continue;
+                };
+                let Ok(mut tile) = tiles.get_mut(entity) else {
+                    continue;
+                };
+                if !tile.solid || f32::from(block(tile.fg_id).required_tier) >= explosion.power {
+                    continue;
+                }
+
+                if !creative.0 {
+                    roll_drops(tile.fg_id, &mut rng, &mut item_events);
+                }
+                let debris_color =
+                    Color::from(block(tile.fg_id).display_color(accessibility.colorblind_palette));
+                spawn_dust(
+                    &mut commands,
+                    &mut rng,
+                    tile_center(coord),
+                    debris_color,
+                    DEBRIS_PER_TILE,
+                );
+                commands.entity(entity).remove::<BreakTimer>();
+                let emptied = tile.emptied();
+                set_tile(&mut tile, emptied);
+            }
+        }
+
+        // Push dynamic bodies away from the center, less the further away they are
+        let knockback_range = explosion.radius * KNOCKBACK_RANGE_FACTOR;
+        for (transform, rigid_body, mut velocity) in &mut bodies {
+            if !matches!(rigid_body, RigidBody::Dynamic) {
+                continue;
+            }
+            let offset = transform.translation.truncate() - explosion.center;
+            let falloff = 1. - offset.length() / knockback_range;
+            if falloff <= 0. {
+                continue;
+            }
+            velocity.0 += offset.normalize_or(Vec2::Y)
+                * explosion.power
+                * KNOCKBACK_SPEED_PER_POWER
+                * falloff;
+        }
+
+        shake.add_trauma(explosion.power * TRAUMA_PER_POWER);
+    }
+}
+
+/// Set off an explosion at the cursor with the middle mouse button while in creative mode, to try
+/// out explosions before there are any explosives
+fn creative_explosions(
+    mouse: Res<ButtonInput<MouseButton>>,
+    creative: Res<CreativeMode>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut explosions: EventWriter<Explosion>,
+    ui_pointer: UiPointer,
+) {
+    if !creative.0 || !mouse.just_pressed(MouseButton::Middle) || ui_pointer.over_ui() {
+        return;
+    }
+    let Some(center) = window
+        .cursor_position()
+        .and_then(|p| camera.0.viewport_to_world_2d(camera.1, p).ok())
+    else {
+        return;
+    };
+
+    explosions.write(Explosion {
+        center,
+        radius: 4.,
+        power: 2.,
     });
-    commands
-        .entity(trigger.target())
-        .insert(Collider::rectangle(1., 1.));
 }
 
+/// Keep colliders on exactly the solid tiles that have at least one open orthogonal neighbor.
+/// Tiles buried inside solid ground can never be touched, so giving them colliders would only
+/// waste physics time.
+fn sync_tile_colliders(
+    changed_tiles: Query<&Transform, Changed<TileData>>,
+    tiles: Query<(&TileData, Has<Collider>)>,
+    game_map: Res<GameMap>,
+    mut commands: Commands,
+) {
+    // A tile changing can expose or bury its neighbors, so they need to be checked as well
+    let mut to_check: HashSet<(i16, i16)> = HashSet::new();
+    for transform in changed_tiles {
+        let coord = tile_coord(&transform.translation.truncate());
+        to_check.insert(coord);
+        to_check.extend(neighbors(coord));
+    }
+
+    let solid_at = |coord| {
+        game_map
+            .tile_at(coord)
+            .and_then(|e| tiles.get(e).ok())
+            .map(|(tile, _)| tile.solid)
+    };
+
+    for coord in to_check {
+        let Some(entity) = game_map.tile_at(coord) else {
+            continue;
+        };
+        let Ok((tile, has_collider)) = tiles.get(entity) else {
+            continue;
+        };
+
+        match (tile.solid && is_exposed(coord, solid_at), has_collider) {
+            (true, false) => {
+                commands.entity(entity).insert(tile_collider());
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<Collider>();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The four orthogonal neighbors of a map coordinate
+fn neighbors((x, y): (i16, i16)) -> [(i16, i16); 4] {
+    [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+}
+
+/// How far inside a tile's faces line_of_sight aims, so the point it aims at is still in the tile
+const SIGHT_INSET: f32 = 0.01;
+/// Check whether any face of a target tile can be seen from a point in world space, given a lookup
+/// for whether the tile at a coordinate is solid. The target tile itself doesn't count as blocking.
+fn line_of_sight(from: Vec2, target: (i16, i16), solid_at: impl Fn((i16, i16)) -> bool) -> bool {
+    // Aiming only at the center would let the corner of the tile next door hide a tile whose top
+    // face is in plain view, so look at the middle of each face instead
+    let reach = 0.5 - SIGHT_INSET;
+    let center = tile_center(target);
+    [
+        Vec2::new(0., reach),
+        Vec2::new(0., -reach),
+        Vec2::new(reach, 0.),
+        Vec2::new(-reach, 0.),
+    ]
+    .into_iter()
+    .any(|offset| {
+        tiles_along_line(from, center + offset).all(|coord| coord == target || !solid_at(coord))
+    })
+}
+
+/// Return every tile a straight line between two points in world space passes through, in order,
+/// leaving out the tile the line starts in. Each tile shares an edge with the one before it, and
+/// the last one is the tile the line ends in.
+fn tiles_along_line(from: Vec2, to: Vec2) -> impl Iterator<Item = (i16, i16)> {
+    // Walk the grid one tile at a time along the line (Amanatides & Woo), always crossing
+    // whichever tile boundary the line reaches first
+    let direction = to - from;
+    let target = tile_coord(&to);
+    let (mut x, mut y) = tile_coord(&from);
+    let step_x: i16 = if direction.x < 0. { -1 } else { 1 };
+    let step_y: i16 = if direction.y < 0. { -1 } else { 1 };
+
+    // How far along the line (0 at the start, 1 at the target) the next boundary on each axis is,
+    // and how far apart boundaries on each axis are. Tile (x, y) spans x..x+1 and y-1..y.
+    let boundary_x = f32::from(if step_x > 0 { x + 1 } else { x });
+    let boundary_y = f32::from(if step_y > 0 { y } else { y - 1 });
+    let (mut next_x, delta_x) = match direction.x {
+        0. => (f32::INFINITY, f32::INFINITY),
+        dx => ((boundary_x - from.x) / dx, 1. / dx.abs()),
+    };
+    let (mut next_y, delta_y) = match direction.y {
+        0. => (f32::INFINITY, f32::INFINITY),
+        dy => ((boundary_y - from.y) / dy, 1. / dy.abs()),
+    };
+
+    // Every step moves one tile closer to the target along one axis, so this is exactly enough
+    // steps to get there
+    let steps = (target.0 - x).abs() + (target.1 - y).abs();
+    (0..steps).map(move |_| {
+        if next_x < next_y {
+            x += step_x;
+            next_x += delta_x;
+        } else {
+            y += step_y;
+            next_y += delta_y;
+        }
+        (x, y)
+    })
+}
+
+/// Check whether a block placed in an open tile would have something to attach to, given a lookup
+/// for whether the tile at a coordinate is solid. Either a solid orthogonal neighbor or a wall
+/// behind the tile will do.
+fn has_support(coord: (i16, i16), tile: &TileData, solid_at: impl Fn((i16, i16)) -> bool) -> bool {
+    tile.bg_id != 0 || neighbors(coord).into_iter().any(solid_at)
+}
+
+/// Check whether a tile has an orthogonal neighbor that isn't solid, given a lookup for whether
+/// the tile at a coordinate is solid. Coordinates off the edge of the map count as open.
+fn is_exposed(coord: (i16, i16), solid_at: impl Fn((i16, i16)) -> Option<bool>) -> bool {
+    neighbors(coord)
+        .into_iter()
+        .any(|neighbor| !solid_at(neighbor).unwrap_or(false))
+}
+
+/// Points from a tile to the entity drawing its background
+#[derive(Component)]
+struct TileBackground(Entity);
+
+/// Marker component for the sprites drawn behind tiles to show their backgrounds
+#[derive(Component)]
+struct BackgroundSprite;
+
+/// Tint applied to background sprites so they read as further away than the foreground
+const BACKGROUND_DIM_COLOR: Color = Color::srgb(0.5, 0.5, 0.5);
+
 /// Modify the Sprites of Entities with TileData Components that were just spawned or modified
 fn tile_sprite_updates(
-    tiles: Query<(&TileData, &mut Sprite), Changed<TileData>>,
+    tiles: Query<
+        (
+            &TileData,
+            &TileBackground,
+            &Transform,
+            &mut Sprite,
+            &mut Visibility,
+        ),
+        Changed<TileData>,
+    >,
+    mut backgrounds: Query<&mut Sprite, (With<BackgroundSprite>, Without<TileData>)>,
     textures: Res<TileAssets>,
+    sky: Res<SkyGradient>,
+    accessibility: Res<AccessibilitySettings>,
 ) {
     for tile in tiles {
-        let (tile_data, mut sprite) = tile;
-        sprite.image = textures.handles.get(tile_data.fg_id).unwrap().clone();
+        let (tile_data, background, transform, sprite, mut visibility) = tile;
+        set_foreground_sprite(
+            sprite,
+            tile_data.fg_id,
+            accessibility.colorblind_palette,
+            &textures,
+        );
+
+        // Tiles without a foreground block are see-through so their background shows
+        visibility.set_if_neq(match tile_data.fg_id {
+            0 => Visibility::Hidden,
+            _ => Visibility::Inherited,
+        });
+
+        let Ok(background_sprite) = backgrounds.get_mut(background.0) else {
+            continue;
+        };
+        let (_, row) = tile_coord(&transform.translation.truncate());
+        set_background_sprite(background_sprite, tile_data.bg_id, row, &textures, &sky);
+    }
+}
+
+/// Point a tile's sprite at the image for its fg_id. The block textures lean on red and green to
+/// tell dirt from grass, so the colorblind palette draws each block as a flat fill of its
+/// colorblind color instead.
+fn set_foreground_sprite(
+    mut sprite: Mut<Sprite>,
+    fg_id: usize,
+    colorblind: bool,
+    textures: &TileAssets,
+) {
+    let (image, color) = match colorblind {
+        true => (
+            Handle::default(),
+            Color::from(block(fg_id).colorblind_color),
+        ),
+        false => (textures.handles.get(fg_id).unwrap().clone(), Color::WHITE),
+    };
+
+    // TileData can change without changing how the tile looks, so only touch the sprite when
+    // something is actually different. Reading through Mut doesn't mark the sprite as changed.
+    if sprite.image != image {
+        sprite.image = image;
+    }
+    if sprite.color != color {
+        sprite.color = color;
+    }
+}
+
+/// Redraw every tile in the palette that was just switched to. Tiles being broken pick the new
+/// palette up from tile_breaking_effect on their next tick.
+fn update_tile_palette(
+    tiles: Query<(&TileData, &mut Sprite), Without<BreakTimer>>,
+    textures: Res<TileAssets>,
+    accessibility: Res<AccessibilitySettings>,
+) {
+    for (tile_data, sprite) in tiles {
+        set_foreground_sprite(
+            sprite,
+            tile_data.fg_id,
+            accessibility.colorblind_palette,
+            &textures,
+        );
+    }
+}
+
+/// Colors of the sky, blended by height from the surface up to the top of the map
+#[derive(Resource)]
+pub struct SkyGradient {
+    pub horizon: Color,
+    pub zenith: Color,
+}
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        SkyGradient {
+            horizon: Color::from(CYAN_400),
+            zenith: Color::from(SKY_800),
+        }
+    }
+}
+
+impl SkyGradient {
+    /// Return the color of the sky at a tile row
+    fn color_at(&self, row: i16) -> Color {
+        let height_frac = (f32::from(row) / f32::from(TOP_EDGE)).clamp(0., 1.);
+        self.horizon.mix(&self.zenith, height_frac)
+    }
+}
+
+/// Point a background sprite at the wall image for a bg_id, dimmed so it sits behind the
+/// foreground. Open sky has no wall and is filled with the sky gradient at that row instead.
+fn set_background_sprite(
+    mut sprite: Mut<Sprite>,
+    bg_id: usize,
+    row: i16,
+    textures: &TileAssets,
+    sky: &SkyGradient,
+) {
+    let (image, color) = match bg_id {
+        0 => (Handle::default(), sky.color_at(row)),
+        _ => (
+            textures.handles.get(bg_id).unwrap().clone(),
+            BACKGROUND_DIM_COLOR,
+        ),
+    };
+
+    // Same as the foreground, only write what actually changed
+    if sprite.image != image {
+        sprite.image = image;
+    }
+    if sprite.color != color {
+        sprite.color = color;
+    }
+}
+
+/// Recolor the open sky behind every tile after the sky gradient changes
+fn update_sky_gradient(
+    tiles: Query<(&TileData, &TileBackground, &Transform)>,
+    mut backgrounds: Query<&mut Sprite, With<BackgroundSprite>>,
+    textures: Res<TileAssets>,
+    sky: Res<SkyGradient>,
+) {
+    for (tile_data, background, transform) in tiles {
+        let Ok(background_sprite) = backgrounds.get_mut(background.0) else {
+            continue;
+        };
+        let (_, row) = tile_coord(&transform.translation.truncate());
+        set_background_sprite(background_sprite, tile_data.bg_id, row, &textures, &sky);
     }
 }
 
 /// Update a tile's sprite while it's being broken
-fn tile_breaking_effect(tiles: Query<(&TileData, &BreakTimer, &mut Sprite), Changed<BreakTimer>>) {
-    // TODO: More bad color picking by id that will only get worse
+fn tile_breaking_effect(
+    tiles: Query<(&TileData, &BreakTimer, &mut Sprite), Changed<BreakTimer>>,
+    accessibility: Res<AccessibilitySettings>,
+) {
     for tile in tiles {
         let (tile_data, break_timer, mut sprite) = tile;
 
-        let base_color = Color::from(match tile_data.fg_id {
-            1 => AMBER_700,
-            2 => GREEN_700,
-            _ => STONE_500,
+        // Each block cracks toward a color that contrasts with it, and high contrast mode uses one
+        // bright overlay color that stands out on everything
+        let overlay_color = Color::from(match accessibility.high_contrast {
+            true => YELLOW_300,
+            false => block(tile_data.fg_id).crack_color,
         });
 
-        let breakage_frac = break_timer.0.elapsed_secs() / BREAK_TIME;
-        sprite.color = base_color.mix(&Color::from(NEUTRAL_950), breakage_frac);
+        let base_color =
+            Color::from(block(tile_data.fg_id).display_color(accessibility.colorblind_palette));
+
+        let breakage_frac = break_timer.progress() / BREAK_TIME;
+        sprite.color = base_color.mix(&overlay_color, breakage_frac);
+    }
+}
+
+/// How long a partially broken tile keeps its progress after the player stops working on it
+const BREAK_GRACE_PERIOD: f32 = 0.25;
+/// Forget the progress on tiles the player has moved away from so abandoned tiles don't stay
+/// cracked forever
+fn decay_break_timers(
+    mut commands: Commands,
+    break_timers: Query<(Entity, &mut BreakTimer)>,
+    time_fixed: Res<Time<Fixed>>,
+) {
+    // tile_destruction resets the idle stopwatch of whichever tile is being worked on, so anything
+    // that keeps ticking up here has been abandoned
+    for (tile_entity, mut break_timer) in break_timers {
+        break_timer.idle.tick(time_fixed.timestep());
+        if break_timer.idle.elapsed_secs() >= BREAK_GRACE_PERIOD {
+            commands.entity(tile_entity).remove::<BreakTimer>();
+        }
+    }
+}
+
+/// Restore the normal sprite of tiles that are no longer being broken
+fn reset_break_effect(
+    mut removed: RemovedComponents<BreakTimer>,
+    mut tiles: Query<(&TileData, &mut Sprite)>,
+    textures: Res<TileAssets>,
+    accessibility: Res<AccessibilitySettings>,
+) {
+    for tile_entity in removed.read() {
+        let Ok((tile_data, sprite)) = tiles.get_mut(tile_entity) else {
+            continue;
+        };
+        set_foreground_sprite(
+            sprite,
+            tile_data.fg_id,
+            accessibility.colorblind_palette,
+            &textures,
+        );
     }
 }
 
 const BLOCKS_X: i16 = 80;
 const BLOCKS_Y: i16 = 80;
-/// Run on application setup to build the map data structure and spawn tile entities
-fn build_terrain(mut game_map: ResMut<GameMap>, mut commands: Commands) {
-    // Blocks are spawned from bottom-left to top-right. BLOCKS_X determines leftmost coordinate.
-    for i in (-BLOCKS_X / 2)..(BLOCKS_X / 2) {
-        for j in (-BLOCKS_Y / 2)..(BLOCKS_Y / 2) {
+/// Map edges in tile coordinates, inclusive on both ends. The far edges are derived from the near
+/// ones so the map is exactly BLOCKS_X by BLOCKS_Y tiles whether the dimensions are odd or even.
+const LEFT_EDGE: i16 = -BLOCKS_X / 2;
+const RIGHT_EDGE: i16 = LEFT_EDGE + BLOCKS_X - 1;
+const BOTTOM_EDGE: i16 = -BLOCKS_Y / 2;
+const TOP_EDGE: i16 = BOTTOM_EDGE + BLOCKS_Y - 1;
+const _: () =
+    assert!(RIGHT_EDGE - LEFT_EDGE + 1 == BLOCKS_X && TOP_EDGE - BOTTOM_EDGE + 1 == BLOCKS_Y);
+// Generation puts the surface on row 0 and the player spawns in column 0, so the map has to cover
+// the origin. This also rules out empty maps, and fails the build rather than panicking at startup.
+const _: () = assert!(
+    LEFT_EDGE <= 0 && RIGHT_EDGE >= 0 && BOTTOM_EDGE <= 0 && TOP_EDGE >= 0,
+    "the map must contain the tile at the origin"
+);
+/// Generate the tile layout of a new map without touching the ECS, so the shape of the map can be
+/// inspected on its own. The surface is always flat grass on row 0 over dirt; the seed only decides
+/// how deep the dirt goes in each column (8 to 12 tiles) before turning to stone. The same seed
+/// always generates the same map.
+pub fn generate_map(seed: u64) -> HashMap<(i16, i16), TileData> {
+    let mut map_data: HashMap<(i16, i16), TileData> = HashMap::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Blocks are laid out from bottom-left to top-right
+    for i in LEFT_EDGE..=RIGHT_EDGE {
+        // How deep the dirt goes before turning to stone varies from column to column
+        let stone_top = -rng.gen_range(8..=12);
+        for j in BOTTOM_EDGE..=TOP_EDGE {
             // Initial tile state depends on y value
             let tile_data = match j {
                 1.. => TileData::default(),
@@ -226,41 +1343,708 @@ fn build_terrain(mut game_map: ResMut<GameMap>, mut commands: Commands) {
                     bg_id: 1,
                     solid: true,
                 },
-                -10..0 => TileData {
+                j if j > stone_top => TileData {
                     fg_id: 1,
                     bg_id: 1,
                     solid: true,
                 },
-                ..-10 => TileData {
+                _ => TileData {
                     fg_id: 3,
                     bg_id: 3,
                     solid: true,
                 },
             };
+            map_data.insert((i, j), tile_data);
+        }
+    }
 
-            // Presence of a collider depends on block state
-            let collider = match j < 1 {
-                true => Some(Collider::rectangle(1., 1.)),
-                false => None,
-            };
+    map_data
+}
+
+/// Return the y coordinate of the highest solid tile in each column of a generated map, keyed by
+/// column. Columns with no solid tiles are left out.
+fn column_surfaces(map_data: &HashMap<(i16, i16), TileData>) -> HashMap<i16, i16> {
+    let mut surface_heights: HashMap<i16, i16> = HashMap::new();
+    for (&(x, y), tile_data) in map_data {
+        if !tile_data.solid {
+            continue;
+        }
+        surface_heights
+            .entry(x)
+            .and_modify(|height| *height = (*height).max(y))
+            .or_insert(y);
+    }
+    surface_heights
+}
+
+/// Run on application setup to build the map data structure and spawn tile entities
+// This is an exclusive system so tiles can be spawned with World::spawn_batch, which is much
+// cheaper than queueing a spawn command (and a collider insert) for every single tile
+fn build_terrain(world: &mut World) {
+    let seed = world.get_resource_or_init::<WorldSeed>().0;
+    let map_data = generate_map(seed);
+
+    let surface_heights = column_surfaces(&map_data);
+
+    // Summarize what was generated. This is logged at debug level so it's quiet by default.
+    let count_of = |fg_id: usize| {
+        map_data
+            .values()
+            .filter(|tile| tile.solid && tile.fg_id == fg_id)
+            .count()
+    };
+    debug!(
+        "Generated {} tiles: {} grass, {} dirt, {} stone, {} open. Surface height ranges from {:?} to {:?}",
+        map_data.len(),
+        count_of(2),
+        count_of(1),
+        count_of(3),
+        map_data.values().filter(|tile| !tile.solid).count(),
+        surface_heights.values().min(),
+        surface_heights.values().max(),
+    );
+
+    // Only solid tiles with an exposed side get a collider, so sort tiles into separate batches
+    let (collidable_tiles, plain_tiles): (Vec<_>, Vec<_>) = map_data
+        .iter()
+        .map(|(&coord, &tile_data)| (coord, tile_data))
+        .partition(|&(coord, tile_data)| {
+            tile_data.solid && is_exposed(coord, |n| map_data.get(&n).map(|t| t.solid))
+        });
+
+    // Spawn a background sprite behind every tile first so the tiles can point to theirs
+    let background_entities: Vec<Entity> = world
+        .spawn_batch(map_data.keys().map(|&coord| background_bundle(coord)))
+        .collect();
+    let backgrounds: HashMap<(i16, i16), Entity> =
+        map_data.keys().copied().zip(background_entities).collect();
+
+    // Spawn tiles in the world
+    let collidable_entities: Vec<Entity> = world
+        .spawn_batch(collidable_tiles.iter().map(|&(coord, tile_data)| {
+            (
+                tile_bundle(coord, tile_data, backgrounds[&coord]),
+                tile_collider(),
+            )
+        }))
+        .collect();
+    let plain_entities: Vec<Entity> = world
+        .spawn_batch(
+            plain_tiles
+                .iter()
+                .map(|&(coord, tile_data)| tile_bundle(coord, tile_data, backgrounds[&coord])),
+        )
+        .collect();
+
+    // Add the tiles to the map resource
+    let mut game_map = world.resource_mut::<GameMap>();
+    game_map.0.extend(
+        collidable_tiles
+            .iter()
+            .map(|&(coord, _)| coord)
+            .zip(collidable_entities),
+    );
+    game_map.0.extend(
+        plain_tiles
+            .iter()
+            .map(|&(coord, _)| coord)
+            .zip(plain_entities),
+    );
 
-            // Spawn tile in the world
-            let tile_entity = commands
-                .spawn((
-                    tile_data,
-                    RigidBody::Static,
-                    Sprite::sized(Vec2::new(1., 1.)),
-                    Transform::from_xyz(f32::from(i) + 0.5, f32::from(j) - 0.5, -1.),
-                ))
-                .id();
-
-            // Add the collider if the tile is solid
-            if let Some(c) = collider {
-                commands.entity(tile_entity).insert(c);
+    world.send_event(WorldGenerated {
+        bounds: IRect::new(
+            LEFT_EDGE.into(),
+            BOTTOM_EDGE.into(),
+            RIGHT_EDGE.into(),
+            TOP_EDGE.into(),
+        ),
+        surface_heights: surface_heights.clone(),
+    });
+    world.insert_resource(SurfaceHeights(surface_heights));
+}
+
+/// Request to throw away the current world and build a fresh one in its place, without restarting
+#[derive(Event)]
+pub struct RegenerateWorld {
+    /// Seed to generate the new world from, or None to keep the current one
+    pub seed: Option<u64>,
+}
+
+/// Regenerate the world from a new random seed when Ctrl+R is pressed, for iterating on generation
+fn regenerate_on_key(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut regenerate_events: EventWriter<RegenerateWorld>,
+) {
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl && keyboard.just_pressed(KeyCode::KeyR) {
+        regenerate_events.write(RegenerateWorld {
+            seed: Some(rand::random()),
+        });
+    }
+}
+
+/// Despawn every tile and build the map again from scratch. Tiles take their break timers with
+/// them, and build_terrain's WorldGenerated event puts the player back at the spawn point.
+fn regenerate_world(world: &mut World) {
+    // Several requests in one frame only need one rebuild, using the last seed that was asked for
+    let mut requested = false;
+    let mut new_seed = None;
+    for event in world.resource_mut::<Events<RegenerateWorld>>().drain() {
+        requested = true;
+        new_seed = event.seed.or(new_seed);
+    }
+    if !requested {
+        return;
+    }
+
+    if let Some(seed) = new_seed {
+        world.insert_resource(WorldSeed(seed));
+        let rng = GameRng::from_world(world);
+        world.insert_resource(rng);
+    }
+
+    let old_tiles: Vec<Entity> = world
+        .query_filtered::<Entity, Or<(With<TileData>, With<BackgroundSprite>)>>()
+        .iter(world)
+        .collect();
+    for entity in old_tiles {
+        world.despawn(entity);
+    }
+    world.resource_mut::<GameMap>().0.clear();
+    // Undo history and the placement drag refer to tiles that no longer exist
+    world.insert_resource(TileEdits::default());
+    world.insert_resource(LastPlacement::default());
+
+    build_terrain(world);
+}
+
+/// Return the area of world space covered by the map
+pub fn map_world_rect() -> Rect {
+    // Tile (x, y) covers world space from x to x + 1 horizontally and y - 1 to y vertically
+    Rect::new(
+        f32::from(LEFT_EDGE),
+        f32::from(BOTTOM_EDGE) - 1.,
+        f32::from(RIGHT_EDGE) + 1.,
+        f32::from(TOP_EDGE),
+    )
+}
+
+/// Marker component for the invisible walls that keep bodies inside the map
+#[derive(Component)]
+struct MapBoundary;
+
+/// How far above the top of the map the side walls extend, so they can't be jumped over
+const BOUNDARY_HEADROOM: f32 = 20.;
+/// Spawn invisible static walls along the left, right, and bottom edges of the map
+fn spawn_map_boundaries(mut commands: Commands) {
+    let map = map_world_rect();
+    let (left, right, bottom) = (map.min.x, map.max.x, map.min.y);
+    let top = map.max.y + BOUNDARY_HEADROOM;
+
+    // Each wall is a single tile thick and sits just outside the map
+    let walls = [
+        Rect::new(left - 1., bottom - 1., left, top),
+        Rect::new(right, bottom - 1., right + 1., top),
+        Rect::new(left - 1., bottom - 1., right + 1., bottom),
+    ];
+    for wall in walls {
+        commands.spawn((
+            MapBoundary,
+            RigidBody::Static,
+            Collider::rectangle(wall.width(), wall.height()),
+            Transform::from_translation(wall.center().extend(0.)),
+        ));
+    }
+}
+
+/// Components shared by every tile entity
+fn tile_bundle(
+    coord: (i16, i16),
+    tile_data: TileData,
+    background: Entity,
+) -> (TileData, TileBackground, RigidBody, Sprite, Transform) {
+    (
+        tile_data,
+        TileBackground(background),
+        RigidBody::Static,
+        Sprite::sized(Vec2::ONE),
+        Transform::from_translation(tile_center(coord).extend(ZLayer::Tile.z())),
+    )
+}
+
+/// Components for the sprite drawn behind a tile. Its image is filled in by tile_sprite_updates
+/// along with the tile's own.
+fn background_bundle(coord: (i16, i16)) -> (BackgroundSprite, Sprite, Transform) {
+    (
+        BackgroundSprite,
+        Sprite::sized(Vec2::ONE),
+        Transform::from_translation(tile_center(coord).extend(ZLayer::Background.z())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inventory::ItemStack,
+        player::{CROUCH_HEIGHT, PLAYER_HEIGHT, player_collider},
+    };
+
+    /// Solid lookup for a flat world whose surface is row 0
+    fn flat_ground((_, y): (i16, i16)) -> bool {
+        y <= 0
+    }
+
+    /// Build an app holding a small map, columns 0 to 4 and rows -4 to 0, with solid dirt wherever
+    /// `solid` says so. Colliders are synced once before it's returned.
+    fn tile_app(solid: impl Fn((i16, i16)) -> bool) -> App {
+        let mut app = App::new();
+        app.add_systems(Update, sync_tile_colliders);
+
+        let mut game_map = GameMap::default();
+        for x in 0..5 {
+            for y in -4..=0 {
+                let tile_data = match solid((x, y)) {
+                    true => TileData::default().with_block(1),
+                    false => TileData::default(),
+                };
+                let entity = app
+                    .world_mut()
+                    .spawn(tile_bundle((x, y), tile_data, Entity::PLACEHOLDER))
+                    .id();
+                game_map.0.insert((x, y), entity);
             }
+        }
+        app.insert_resource(game_map);
+        app.update();
+        app
+    }
+
+    fn tile_entity(app: &App, coord: (i16, i16)) -> Entity {
+        app.world().resource::<GameMap>().tile_at(coord).unwrap()
+    }
 
-            // Add the tile to the map resource
-            game_map.0.insert((i, j), tile_entity);
+    fn has_collider(app: &App, coord: (i16, i16)) -> bool {
+        app.world()
+            .get::<Collider>(tile_entity(app, coord))
+            .is_some()
+    }
+
+    /// Change a tile through set_tile and let the colliders catch up
+    fn change_tile(
+        app: &mut App,
+        coord: (i16, i16),
+        change: impl FnOnce(TileData) -> TileData,
+    ) -> TileData {
+        let entity = tile_entity(app, coord);
+        let mut tile = app.world_mut().get_mut::<TileData>(entity).unwrap();
+        let new_data = change(*tile);
+        let before = set_tile(&mut tile, new_data);
+        app.update();
+        before
+    }
+
+    fn tile_data(app: &App, coord: (i16, i16)) -> TileData {
+        *app.world()
+            .get::<TileData>(tile_entity(app, coord))
+            .unwrap()
+    }
+
+    #[test]
+    fn tiles_along_line_walks_edge_to_edge() {
+        let start = (0, 0);
+        let target = (5, -3);
+        let path: Vec<_> = tiles_along_line(tile_center(start), tile_center(target)).collect();
+
+        assert!(!path.contains(&start));
+        assert_eq!(path.last(), Some(&target));
+        for (a, b) in std::iter::once(start)
+            .chain(path.iter().copied())
+            .zip(&path)
+        {
+            assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1, "{a:?} to {b:?}");
         }
     }
+
+    #[test]
+    fn tiles_along_line_within_one_tile_is_empty() {
+        assert_eq!(
+            tiles_along_line(tile_center((2, 2)), tile_center((2, 2))).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn surface_is_visible_on_flat_ground() {
+        // Standing on row 0 puts the player's center a tile and a half above the surface
+        let eye = Vec2::new(0.5, 1.5);
+        for x in -6..=6 {
+            assert!(line_of_sight(eye, (x, 0), flat_ground), "surface tile {x}");
+        }
+    }
+
+    #[test]
+    fn buried_tiles_are_hidden_on_flat_ground() {
+        let eye = Vec2::new(0.5, 1.5);
+        assert!(!line_of_sight(eye, (3, -1), flat_ground));
+        assert!(!line_of_sight(eye, (0, -2), flat_ground));
+    }
+
+    #[test]
+    fn tier_gap_sets_break_rate() {
+        assert_eq!(tier_break_rate(0, 0), Some(1.));
+        assert_eq!(tier_break_rate(1, 3), Some(1.));
+        assert_eq!(tier_break_rate(1, 0), Some(UNDER_TIER_BREAK_RATE));
+        assert_eq!(tier_break_rate(3, 2), Some(UNDER_TIER_BREAK_RATE));
+        assert_eq!(tier_break_rate(2, 0), None);
+        assert_eq!(tier_break_rate(5, 1), None);
+    }
+
+    #[test]
+    fn held_stone_mines_stone_at_full_speed() {
+        let toolbar = Toolbar::default();
+        let mut inventory = Inventory::default();
+        assert_eq!(break_rate(&inventory, &toolbar, 1), Some(1.));
+        assert_eq!(
+            break_rate(&inventory, &toolbar, 3),
+            Some(UNDER_TIER_BREAK_RATE)
+        );
+
+        inventory.0[toolbar.selected] = Some(ItemStack::new(3, 1));
+        assert_eq!(break_rate(&inventory, &toolbar, 3), Some(1.));
+    }
+
+    #[test]
+    fn tile_collider_matches_sprite() {
+        let coord = (-7, 3);
+        let (_, _, _, sprite, transform) =
+            tile_bundle(coord, TileData::default(), Entity::PLACEHOLDER);
+        let center = transform.translation.truncate();
+        let sprite_rect = Rect::from_center_size(center, sprite.custom_size.unwrap());
+
+        let aabb = tile_collider().aabb(center, Rotation::default());
+        assert_eq!(Rect::from_corners(aabb.min, aabb.max), sprite_rect);
+        // The tile at (x, y) covers x..x + 1 and y - 1..y
+        assert_eq!(sprite_rect, Rect::new(-7., 2., -6., 3.));
+    }
+
+    #[test]
+    fn set_tile_updates_data_and_collider() {
+        let mut app = tile_app(flat_ground);
+        assert!(has_collider(&app, (2, 0)));
+
+        let before = change_tile(&mut app, (2, 0), TileData::emptied);
+        assert!(before.solid);
+        assert_eq!(
+            tile_data(&app, (2, 0)),
+            TileData::default().with_block(1).emptied()
+        );
+        assert!(!has_collider(&app, (2, 0)));
+
+        let before = change_tile(&mut app, (2, 0), |tile| tile.with_block(3));
+        assert!(!before.solid);
+        assert_eq!(tile_data(&app, (2, 0)).fg_id(), 3);
+        assert!(has_collider(&app, (2, 0)));
+    }
+
+    #[test]
+    fn only_exposed_tiles_have_colliders() {
+        let app = tile_app(|_| true);
+        for x in 0..5 {
+            for y in -4..=0 {
+                // Everything past the edge of the map counts as open, so only the middle is buried
+                let buried = (1..=3).contains(&x) && (-3..=-1).contains(&y);
+                assert_eq!(has_collider(&app, (x, y)), !buried, "tile ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_gain_and_lose_colliders() {
+        let mut app = tile_app(|_| true);
+        assert!(!has_collider(&app, (2, -1)));
+
+        // Breaking a tile exposes the one below it
+        change_tile(&mut app, (2, 0), TileData::emptied);
+        assert!(has_collider(&app, (2, -1)));
+
+        // Filling it back in buries it again
+        change_tile(&mut app, (2, 0), |tile| tile.with_block(1));
+        assert!(!has_collider(&app, (2, -1)));
+    }
+
+    #[test]
+    fn off_map_neighbors_count_as_open() {
+        assert!(is_exposed((0, 0), |(x, _)| (x == 0).then_some(true)));
+        assert!(!is_exposed((0, 0), |_| Some(true)));
+        assert!(is_exposed((0, 0), |c| Some(c != (0, -1))));
+    }
+
+    /// Add undo to a tile app, with a player far away from the map holding an inventory
+    fn undo_app(app: &mut App, inventory: Inventory) {
+        app.add_plugins(crate::inventory::InventoryPlugin)
+            .add_systems(Update, undo_last)
+            .init_resource::<TileEdits>()
+            .init_resource::<ButtonInput<KeyCode>>();
+        app.world_mut().spawn((
+            Player,
+            inventory,
+            Transform::from_xyz(100., 100., 0.),
+            Collider::rectangle(2., 3.),
+        ));
+    }
+
+    /// Press Ctrl+Z for a frame, then give the inventory a frame to catch up
+    fn press_undo(app: &mut App) {
+        let mut keyboard = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+        keyboard.press(KeyCode::ControlLeft);
+        keyboard.press(KeyCode::KeyZ);
+        app.update();
+        let mut keyboard = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+        keyboard.release_all();
+        keyboard.clear();
+        app.update();
+    }
+
+    fn inventory_slot(app: &mut App, slot: usize) -> Option<(usize, usize)> {
+        let mut inventories = app.world_mut().query::<&Inventory>();
+        inventories.single(app.world()).unwrap().0[slot].map(|s| (s.item_id, s.count))
+    }
+
+    #[test]
+    fn undoing_a_placement_removes_the_block_and_refunds_it() {
+        let mut app = tile_app(flat_ground);
+        undo_app(&mut app, Inventory::default());
+
+        let before = change_tile(&mut app, (2, 1), |tile| tile.with_block(1));
+        app.world_mut()
+            .resource_mut::<TileEdits>()
+            .record(TileEdit {
+                coord: (2, 1),
+                before,
+                after: tile_data(&app, (2, 1)),
+                item_spent: Some(1),
+                items_gained: Vec::new(),
+            });
+        press_undo(&mut app);
+
+        assert_eq!(tile_data(&app, (2, 1)), before);
+        assert_eq!(inventory_slot(&mut app, 0), Some((1, 1)));
+        assert!(app.world().resource::<TileEdits>().0.is_empty());
+    }
+
+    #[test]
+    fn undoing_a_break_restores_the_block_and_takes_the_drop() {
+        let mut app = tile_app(flat_ground);
+        let mut inventory = Inventory::default();
+        inventory.0[0] = Some(ItemStack::new(1, 1));
+        undo_app(&mut app, inventory);
+
+        let before = change_tile(&mut app, (2, 0), TileData::emptied);
+        app.world_mut()
+            .resource_mut::<TileEdits>()
+            .record(TileEdit {
+                coord: (2, 0),
+                before,
+                after: tile_data(&app, (2, 0)),
+                item_spent: None,
+                items_gained: vec![1],
+            });
+        press_undo(&mut app);
+
+        assert_eq!(tile_data(&app, (2, 0)), before);
+        assert!(has_collider(&app, (2, 0)));
+        assert_eq!(inventory_slot(&mut app, 0), None);
+    }
+
+    #[test]
+    fn undo_history_forgets_the_oldest_edits() {
+        let mut edits = TileEdits::default();
+        let total = UNDO_HISTORY as i16 + 8;
+        for x in 0..total {
+            edits.record(TileEdit {
+                coord: (x, 0),
+                before: TileData::default(),
+                after: TileData::default().with_block(1),
+                item_spent: None,
+                items_gained: Vec::new(),
+            });
+        }
+
+        assert_eq!(edits.0.len(), UNDO_HISTORY);
+        assert_eq!(edits.0.front().unwrap().coord, (8, 0));
+        assert_eq!(edits.0.back().unwrap().coord, (total - 1, 0));
+    }
+
+    #[test]
+    fn explosions_break_weak_tiles_in_the_radius() {
+        let mut app = tile_app(|_| true);
+        app.add_event::<Explosion>()
+            .add_event::<ItemPickedUp>()
+            .init_resource::<GameRng>()
+            .init_resource::<CameraShake>()
+            .init_resource::<CreativeMode>()
+            .init_resource::<AccessibilitySettings>()
+            .add_systems(Update, handle_explosions);
+        // Stone needs a tier 1 tool, so a power 1 blast can't break it
+        change_tile(&mut app, (3, -2), |tile| tile.with_block(3));
+        let body = app
+            .world_mut()
+            .spawn((
+                RigidBody::Dynamic,
+                LinearVelocity::default(),
+                Transform::from_xyz(4.5, -2.5, 0.),
+            ))
+            .id();
+
+        app.world_mut().send_event(Explosion {
+            center: tile_center((2, -2)),
+            radius: 1.5,
+            power: 1.,
+        });
+        app.update();
+
+        // The blast covers the 3x3 block around its center, diagonals included
+        for x in 0..5 {
+            for y in -4..=0 {
+                let in_blast = (1..=3).contains(&x) && (-3..=-1).contains(&y);
+                let broken = in_blast && (x, y) != (3, -2);
+                assert_eq!(
+                    tile_data(&app, (x, y)).is_solid(),
+                    !broken,
+                    "tile ({x}, {y})"
+                );
+            }
+        }
+        // Each broken dirt tile drops one dirt and throws out some debris
+        assert_eq!(app.world().resource::<Events<ItemPickedUp>>().len(), 8);
+        let mut debris = app
+            .world_mut()
+            .query_filtered::<(), (With<Sprite>, Without<TileData>)>();
+        assert_eq!(debris.iter(app.world()).count(), 8 * DEBRIS_PER_TILE);
+        // Bodies nearby are pushed away from the center
+        assert!(app.world().get::<LinearVelocity>(body).unwrap().x > 0.);
+    }
+
+    #[test]
+    fn generated_surface_is_row_zero() {
+        let surfaces = column_surfaces(&generate_map(7));
+        assert_eq!(surfaces.len(), BLOCKS_X as usize);
+        for x in LEFT_EDGE..=RIGHT_EDGE {
+            assert_eq!(surfaces.get(&x), Some(&0), "column {x}");
+        }
+    }
+
+    #[test]
+    fn surface_heights_follow_breaks_and_placements() {
+        let mut app = tile_app(flat_ground);
+        app.init_resource::<SurfaceHeights>()
+            .add_systems(Update, update_surface_heights);
+        app.update();
+        let surface = |app: &App, x| app.world().resource::<SurfaceHeights>().surface_height(x);
+        assert_eq!(surface(&app, 2), Some(0));
+
+        change_tile(&mut app, (2, 0), TileData::emptied);
+        assert_eq!(surface(&app, 2), Some(-1));
+
+        // Placing above a gap raises the surface, and removing it again drops past the gap
+        change_tile(&mut app, (2, -1), TileData::emptied);
+        assert_eq!(surface(&app, 2), Some(-2));
+        change_tile(&mut app, (2, 0), |tile| tile.with_block(1));
+        assert_eq!(surface(&app, 2), Some(0));
+        change_tile(&mut app, (2, 0), TileData::emptied);
+        assert_eq!(surface(&app, 2), Some(-2));
+
+        // A column with nothing solid left has no surface
+        for y in -4..=0 {
+            change_tile(&mut app, (4, y), TileData::emptied);
+        }
+        assert_eq!(surface(&app, 4), None);
+    }
+
+    /// Add block placement to a tile app, in creative mode so blocks don't need support. The
+    /// player stands on row -4 with a collider of the given height, holding dirt.
+    fn placement_app(app: &mut App, player_height: f32) {
+        let mut inventory = Inventory::default();
+        inventory.0[0] = Some(ItemStack::new(1, 10));
+        app.add_observer(tile_placement)
+            .add_event::<ItemRemoved>()
+            .init_resource::<Toolbar>()
+            .init_resource::<TileEdits>()
+            .init_resource::<LastPlacement>()
+            .insert_resource(CreativeMode(true));
+        app.world_mut().spawn((
+            Player,
+            inventory,
+            Transform::from_xyz(2.5, -4. + player_height / 2., 0.),
+            player_collider(player_height),
+        ));
+    }
+
+    /// Try to place the held block in a tile, returning whether it went in
+    fn try_place(app: &mut App, coord: (i16, i16)) -> bool {
+        let entity = tile_entity(app, coord);
+        app.world_mut().trigger_targets(TilePlaced, entity);
+        tile_data(app, coord).is_solid()
+    }
+
+    fn deep_floor((_, y): (i16, i16)) -> bool {
+        y <= -4
+    }
+
+    #[test]
+    fn blocks_cant_be_placed_inside_the_player() {
+        let mut app = tile_app(deep_floor);
+        placement_app(&mut app, PLAYER_HEIGHT);
+
+        // The standing player reaches up into row -1, but not row 0
+        assert!(!try_place(&mut app, (2, -3)));
+        assert!(!try_place(&mut app, (3, -1)));
+        assert!(try_place(&mut app, (2, 0)));
+        // Columns past the player's sides are free
+        assert!(try_place(&mut app, (4, -3)));
+    }
+
+    #[test]
+    fn crouching_frees_the_row_above() {
+        let mut app = tile_app(deep_floor);
+        placement_app(&mut app, CROUCH_HEIGHT);
+
+        assert!(!try_place(&mut app, (2, -2)));
+        assert!(try_place(&mut app, (2, -1)));
+    }
+
+    #[test]
+    fn placed_blocks_need_a_neighbor_or_a_wall() {
+        let open = TileData::default();
+        let walled = TileData {
+            bg_id: 1,
+            ..TileData::default()
+        };
+        let solid_only_at = |solid: (i16, i16)| move |c: (i16, i16)| c == solid;
+
+        // Any orthogonal neighbor will do
+        for neighbor in neighbors((0, 0)) {
+            assert!(has_support((0, 0), &open, solid_only_at(neighbor)));
+        }
+        // Diagonal neighbors don't count
+        assert!(!has_support((0, 0), &open, solid_only_at((1, 1))));
+        assert!(!has_support((0, 0), &open, |_| false));
+        // A background wall holds a block up on its own
+        assert!(has_support((0, 0), &walled, |_| false));
+    }
+
+    #[test]
+    fn placement_is_refused_without_support() {
+        let mut app = tile_app(deep_floor);
+        placement_app(&mut app, PLAYER_HEIGHT);
+        app.insert_resource(CreativeMode(false));
+
+        // Floating in the open sky
+        assert!(!try_place(&mut app, (0, -1)));
+        // Resting on the floor, out of the player's way
+        assert!(try_place(&mut app, (0, -3)));
+        // Stacked on the block that was just placed
+        assert!(try_place(&mut app, (0, -2)));
+    }
 }
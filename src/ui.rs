@@ -1,11 +1,15 @@
 use bevy::{
-    color::palettes::tailwind::{AMBER_700, GREEN_700, STONE_500},
-    prelude::*,
+    ecs::system::SystemParam, input::mouse::AccumulatedMouseScroll, platform::collections::HashMap,
+    prelude::*, ui::widget::TextShadow, window::PrimaryWindow,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    inventory::ItemStack,
-    player::{PLAYER_HEIGHT, Player},
+    blocks::block,
+    inventory::{Inventory, InventoryChanged, ItemStack},
+    player::{CROUCH_HEIGHT, Crouching, PLAYER_HEIGHT, Player},
+    settings::AccessibilitySettings,
+    terrain::{WorldTiles, tile_coord},
 };
 
 pub struct UiPlugin;
@@ -13,11 +17,30 @@ pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Toolbar>()
-            .add_event::<ToolbarSlotUpdate>()
-            .add_systems(Startup, (build_ui, build_toolbar))
+            .init_resource::<ToolbarSize>()
+            .init_resource::<HudSettings>()
+            .add_systems(Startup, build_ui)
             .add_systems(
                 Update,
-                (update_coordinates_ui, keyboard_toolbar, update_toolbar_slot),
+                (
+                    update_coordinates_ui,
+                    update_tile_info_ui,
+                    keyboard_toolbar,
+                    cycle_toolbar,
+                    scroll_toolbar,
+                    // The toolbar is (re)built whenever its size or palette changes, including on
+                    // the first frame, and refilled before slot updates are applied
+                    (
+                        build_toolbar.run_if(
+                            resource_changed::<ToolbarSize>
+                                .or(resource_changed::<AccessibilitySettings>),
+                        ),
+                        update_toolbar_slot,
+                        flash_picked_up_slots,
+                        fade_slot_flashes,
+                    )
+                        .chain(),
+                ),
             );
     }
 }
@@ -26,24 +49,162 @@ impl Plugin for UiPlugin {
 #[require(Text)]
 struct UiCoordinateText;
 
+/// How the on-screen readouts are shown
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HudSettings {
+    /// Show the position of the player's feet as the map coordinate of the tile they're standing
+    /// on, rather than as a world space position
+    pub tile_coordinates: bool,
+    /// Decimal places shown for world space positions
+    pub coordinate_precision: usize,
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        HudSettings {
+            tile_coordinates: false,
+            coordinate_precision: 1,
+        }
+    }
+}
+
 fn update_coordinates_ui(
     mut text: Single<&mut Text, With<UiCoordinateText>>,
-    player: Single<&Transform, With<Player>>,
+    player: Single<(&Transform, Has<Crouching>), With<Player>>,
+    hud: Res<HudSettings>,
 ) {
+    let (transform, crouching) = player.into_inner();
+    let height = match crouching {
+        true => CROUCH_HEIGHT,
+        false => PLAYER_HEIGHT,
+    };
+    let feet = Vec2::new(
+        transform.translation.x,
+        transform.translation.y - height / 2.,
+    );
+
+    text.0 = match hud.tile_coordinates {
+        true => {
+            // Sample just below the feet, since standing exactly on a tile's top edge would
+            // otherwise land on either side of it depending on rounding
+            let (x, y) = tile_coord(&(feet - Vec2::Y * 0.1));
+            format!("({x}, {y})")
+        }
+        false => format!(
+            "({:.*}, {:.*})",
+            hud.coordinate_precision, feet.x, hud.coordinate_precision, feet.y,
+        ),
+    };
+}
+
+/// Text describing the tile under the cursor
+#[derive(Component)]
+#[require(Text)]
+struct UiTileInfoText;
+
+/// Show the coordinate, block and required tool tier of the tile under the cursor, hiding the text
+/// while the cursor is outside the window or off the edge of the map
+fn update_tile_info_ui(
+    text: Single<(&mut Text, &mut Visibility), With<UiTileInfoText>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    world_tiles: WorldTiles,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+
+    let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|p| camera.0.viewport_to_world_2d(camera.1, p).ok())
+        .filter(|world_pos| world_tiles.get_under(world_pos).is_some())
+    else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    // Open tiles are described as air even if there's a wall behind them
+    let coord = tile_coord(&world_pos);
+    let block_def = block(world_tiles.solid_block_under(&world_pos).unwrap_or(0));
+    *visibility = Visibility::Inherited;
     text.0 = format!(
-        "({0:.1}, {1:.1})",
-        player.translation.x,
-        player.translation.y - PLAYER_HEIGHT / 2.,
+        "({}, {})\n{}\nTool tier {}",
+        coord.0, coord.1, block_def.name, block_def.required_tier,
     );
 }
 
 fn build_ui(mut commands: Commands) {
     commands.spawn(UiCoordinateText);
+    commands.spawn((
+        UiTileInfoText,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.),
+            right: Val::Px(0.),
+            ..default()
+        },
+        TextLayout::new_with_justify(JustifyText::Right),
+    ));
 }
 
-/// Create the toolbar
-pub const TOOLBAR_BUTTONS: usize = 5;
-fn build_toolbar(mut commands: Commands, mut toolbar: ResMut<Toolbar>) {
+/// System parameter to check whether the cursor is over the UI, so clicks meant for the UI don't
+/// also reach the world behind it
+#[derive(SystemParam)]
+pub struct UiPointer<'w, 's> {
+    interactions: Query<'w, 's, &'static Interaction>,
+}
+
+impl UiPointer<'_, '_> {
+    /// Check whether the cursor is hovering or pressing any interactive UI node
+    pub fn over_ui(&self) -> bool {
+        self.interactions
+            .iter()
+            .any(|interaction| *interaction != Interaction::None)
+    }
+}
+
+/// Number of slots on the toolbar. Changing this rebuilds the toolbar UI.
+#[derive(Resource)]
+pub struct ToolbarSize(pub usize);
+
+impl Default for ToolbarSize {
+    fn default() -> Self {
+        ToolbarSize(5)
+    }
+}
+
+/// Digit keys that select toolbar slots, in slot order. This also caps how many slots the toolbar
+/// can have.
+const TOOLBAR_KEYS: [KeyCode; 10] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::Digit0,
+];
+
+/// Marker component for the node holding all of the toolbar buttons
+#[derive(Component)]
+struct ToolbarRoot;
+
+/// Create the toolbar, replacing the old one if it already exists
+fn build_toolbar(
+    mut commands: Commands,
+    mut toolbar: ResMut<Toolbar>,
+    toolbar_size: Res<ToolbarSize>,
+    old_roots: Query<Entity, With<ToolbarRoot>>,
+    inventory: Option<Single<&Inventory, With<Player>>>,
+    accessibility: Res<AccessibilitySettings>,
+) {
+    for root in old_roots {
+        commands.entity(root).despawn();
+    }
+
+    let slot_count = toolbar_size.0.clamp(1, TOOLBAR_KEYS.len());
     let toolbar_base = Node {
         margin: UiRect::all(Val::Px(5.)),
         column_gap: Val::Px(10.),
@@ -56,23 +217,36 @@ fn build_toolbar(mut commands: Commands, mut toolbar: ResMut<Toolbar>) {
     let mut icons: Vec<Entity> = Vec::new();
     let mut texts: Vec<Entity> = Vec::new();
 
-    commands.spawn(toolbar_base).with_children(|p| {
-        for _ in 0..TOOLBAR_BUTTONS {
-            buttons.push(
-                p.spawn(ToolbarButtonBundle::default())
-                    .with_children(|p| {
-                        icons.push(p.spawn(ButtonItemIcon::default()).id());
-                        texts.push(p.spawn(ButtonTextLabel::default()).id());
-                    })
-                    .id(),
-            );
-        }
-    });
+    commands
+        .spawn((ToolbarRoot, toolbar_base))
+        .with_children(|p| {
+            for slot in 0..slot_count {
+                // Fill the new slots in from the player's inventory, since they won't get change
+                // events for items that were already there
+                let stack = inventory
+                    .as_ref()
+                    .and_then(|inventory| inventory.0.get(slot).copied().flatten());
+                let (image, text) = slot_contents(stack, &accessibility);
+
+                buttons.push(
+                    p.spawn(ToolbarButtonBundle::default())
+                        .with_children(|p| {
+                            icons.push(p.spawn(ButtonItemIcon { image, ..default() }).id());
+                            texts.push(p.spawn(ButtonTextLabel { text, ..default() }).id());
+                        })
+                        .id(),
+                );
+            }
+        });
 
     // Move the Vecs to the Resource things
     toolbar.buttons = buttons;
     toolbar.icons = icons;
     toolbar.text = texts;
+
+    // Keep the selection on the toolbar if it shrank, and highlight it on the new buttons
+    let selected = toolbar.selected.min(slot_count - 1);
+    select_toolbar_slot(&mut toolbar, &mut commands, selected);
 }
 
 #[derive(Resource, Default)]
@@ -83,50 +257,117 @@ pub struct Toolbar {
     pub selected: usize,
 }
 
-#[derive(Event)]
-pub struct ToolbarSlotUpdate {
-    pub stack: Option<ItemStack>,
-    pub slot: usize,
-}
-
+/// Show inventory changes on the toolbar slots they affect
 fn update_toolbar_slot(
-    mut events: EventReader<ToolbarSlotUpdate>,
+    mut events: EventReader<InventoryChanged>,
     toolbar: Res<Toolbar>,
     mut commands: Commands,
+    accessibility: Res<AccessibilitySettings>,
 ) {
+    // Several changes can land on the same slot in one frame (e.g. a block dropping more than one
+    // item), so only the final state of each slot is applied
+    let mut latest: HashMap<usize, Option<ItemStack>> = HashMap::new();
     for e in events.read() {
-        // Try to get the icon entity and text entity from the toolbar. If we can't (e.g. somehow
-        // e.slot is higher than the actual number of toolbar slots), then skip this event.
-        let Some(icon_entity) = toolbar.icons.get(e.slot) else {
-            return;
-        };
-        let Some(text_entity) = toolbar.text.get(e.slot) else {
-            return;
-        };
+        latest.insert(e.slot, e.after);
+    }
 
-        // Get a color based on the item id
-        // In the future this will be more complicated
-        let image_node = match e.stack {
-            Some(s) => ImageNode::solid_color(Color::from(match s.item_id {
-                1 => AMBER_700,
-                2 => GREEN_700,
-                _ => STONE_500,
-            })),
-            None => ImageNode::default(),
+    for (slot, stack) in latest {
+        // Try to get the icon entity and text entity from the toolbar. If we can't (e.g. the
+        // inventory slot isn't on the toolbar), then skip this slot.
+        let Some(icon_entity) = toolbar.icons.get(slot) else {
+            continue;
+        };
+        let Some(text_entity) = toolbar.text.get(slot) else {
+            continue;
         };
-
-        // Get text from the count
-        let text = Text(match e.stack {
-            Some(s) => format!("{}", s.count),
-            None => "".to_owned(),
-        });
 
         // Apply the new properties to the respective entities
+        let (image_node, text) = slot_contents(stack, &accessibility);
         commands.entity(icon_entity.to_owned()).insert(image_node);
         commands.entity(text_entity.to_owned()).insert(text);
     }
 }
 
+/// Present on a toolbar button while its background is flashing after items were added to the slot
+#[derive(Component)]
+struct SlotFlash(Timer);
+
+const SLOT_FLASH_COLOR: Srgba = Srgba::new(1., 1., 1., 0.6);
+const SLOT_FLASH_TIME: f32 = 0.2;
+/// Flash the background of any toolbar slot that gained items, so pickups are noticeable. This
+/// uses the background rather than the border so it doesn't fight with the selection highlight.
+fn flash_picked_up_slots(
+    mut events: EventReader<InventoryChanged>,
+    toolbar: Res<Toolbar>,
+    mut commands: Commands,
+) {
+    let count = |stack: Option<ItemStack>| stack.map_or(0, |s| s.count);
+    for e in events.read() {
+        if count(e.after) <= count(e.before) {
+            continue;
+        }
+        let Some(&button) = toolbar.buttons.get(e.slot) else {
+            continue;
+        };
+        // Inserting over an existing flash restarts it
+        commands.entity(button).insert((
+            SlotFlash(Timer::from_seconds(SLOT_FLASH_TIME, TimerMode::Once)),
+            BackgroundColor::from(SLOT_FLASH_COLOR),
+        ));
+    }
+}
+
+/// Ease flashing toolbar slots back to their normal background
+fn fade_slot_flashes(
+    mut commands: Commands,
+    flashes: Query<(Entity, &mut SlotFlash, &mut BackgroundColor)>,
+    time: Res<Time>,
+) {
+    for (button, mut flash, mut background) in flashes {
+        flash.0.tick(time.delta());
+        let color = Color::from(SLOT_FLASH_COLOR)
+            .mix(&Color::from(TOOLBAR_SLOT_BACKGROUND), flash.0.fraction());
+        background.0 = color;
+        if flash.0.finished() {
+            commands.entity(button).remove::<SlotFlash>();
+        }
+    }
+}
+
+/// Return the icon and count label a toolbar slot should show for a stack
+fn slot_contents(
+    stack: Option<ItemStack>,
+    accessibility: &AccessibilitySettings,
+) -> (ImageNode, Text) {
+    // Items are blocks for now, so show the block's color
+    let image_node = match stack {
+        Some(s) => ImageNode::solid_color(Color::from(
+            block(s.item_id).display_color(accessibility.colorblind_palette),
+        )),
+        None => ImageNode::default(),
+    };
+
+    // Get text from the count
+    let text = Text(match stack {
+        Some(s) => format_count(s.count),
+        None => "".to_owned(),
+    });
+
+    (image_node, text)
+}
+
+/// Format an item count so it fits in a toolbar slot. Small counts are shown exactly and large
+/// ones are abbreviated.
+fn format_count(n: usize) -> String {
+    match n {
+        ..1_000 => n.to_string(),
+        // Truncate rather than round so a label never overstates the count
+        ..10_000 => format!("{}.{}k", n / 1_000, n % 1_000 / 100),
+        ..1_000_000 => format!("{}k", n / 1_000),
+        _ => "999k+".to_owned(),
+    }
+}
+
 /// Marker component for toolbar buttons
 #[derive(Component)]
 struct ToolbarButton;
@@ -135,6 +376,7 @@ struct ToolbarButton;
 /// A bundle to simplify the creation of toolbar buttons with predefined properties
 struct ToolbarButtonBundle {
     marker: ToolbarButton,
+    interaction: Interaction,
     node: Node,
     border_radius: BorderRadius,
     border_color: BorderColor,
@@ -142,10 +384,12 @@ struct ToolbarButtonBundle {
 }
 
 const TOOLBAR_SLOT_SIZE: f32 = 50.;
+const TOOLBAR_SLOT_BACKGROUND: Srgba = Srgba::new(0.0, 0.0, 0.0, 0.4);
 impl Default for ToolbarButtonBundle {
     fn default() -> Self {
         ToolbarButtonBundle {
             marker: ToolbarButton,
+            interaction: Interaction::default(),
             node: Node {
                 height: Val::Px(TOOLBAR_SLOT_SIZE),
                 width: Val::Px(TOOLBAR_SLOT_SIZE),
@@ -155,7 +399,7 @@ impl Default for ToolbarButtonBundle {
             },
             border_radius: BorderRadius::all(Val::Px(5.)),
             border_color: BorderColor::from(Srgba::new(0.1, 0.1, 0.1, 0.6)),
-            background_color: BackgroundColor::from(Srgba::new(0.0, 0.0, 0.0, 0.4)),
+            background_color: BackgroundColor::from(TOOLBAR_SLOT_BACKGROUND),
         }
     }
 }
@@ -171,6 +415,8 @@ struct ButtonTextLabel {
     node: Node,
     text: Text,
     text_font: TextFont,
+    // A dark drop shadow keeps the count readable over light icons
+    text_shadow: TextShadow,
     z_index: ZIndex,
 }
 
@@ -196,6 +442,10 @@ impl Default for ButtonTextLabel {
             text: Text::default(),
             text_font: TextFont::default()
                 .with_line_height(bevy::text::LineHeight::RelativeToFont(1.)),
+            text_shadow: TextShadow {
+                offset: Vec2::splat(1.5),
+                color: Color::from(Srgba::new(0., 0., 0., 0.9)),
+            },
             z_index: ZIndex(1),
         }
     }
@@ -244,25 +494,100 @@ fn keyboard_toolbar(
     mut toolbar: ResMut<Toolbar>,
     mut commands: Commands,
 ) {
-    let new_selected = if keyboard.just_pressed(KeyCode::Digit1) {
-        0
-    } else if keyboard.just_pressed(KeyCode::Digit2) {
-        1
-    } else if keyboard.just_pressed(KeyCode::Digit3) {
-        2
-    } else if keyboard.just_pressed(KeyCode::Digit4) {
-        3
-    } else if keyboard.just_pressed(KeyCode::Digit5) {
-        4
-    } else {
+    // Only as many digit keys as there are toolbar slots do anything
+    let Some(new_selected) = TOOLBAR_KEYS
+        .iter()
+        .take(toolbar.buttons.len())
+        .position(|&key| keyboard.just_pressed(key))
+    else {
         return;
     };
 
-    commands
-        .entity(toolbar.buttons.get(toolbar.selected).unwrap().to_owned())
-        .insert(BorderColor::from(Srgba::new(0., 0., 0., 0.6)));
+    select_toolbar_slot(&mut toolbar, &mut commands, new_selected);
+}
+
+/// Keys that, while held, make the scroll wheel cycle the toolbar instead of zooming the camera
+pub const TOOLBAR_SCROLL_MODIFIERS: [KeyCode; 2] = [KeyCode::ControlLeft, KeyCode::ControlRight];
+
+/// Cycle the selected toolbar slot with the scroll wheel while a modifier is held
+fn scroll_toolbar(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    scroll_input: Res<AccumulatedMouseScroll>,
+    mut toolbar: ResMut<Toolbar>,
+    mut commands: Commands,
+) {
+    let slot_count = toolbar.buttons.len();
+    if !keyboard.any_pressed(TOOLBAR_SCROLL_MODIFIERS)
+        || scroll_input.delta.y == 0.
+        || slot_count == 0
+    {
+        return;
+    }
+
+    // Scrolling down moves the selection right, scrolling up moves it left
+    let new_selected = adjacent_slot(&toolbar, scroll_input.delta.y < 0.);
+    select_toolbar_slot(&mut toolbar, &mut commands, new_selected);
+}
+
+/// Cycle the selected toolbar slot left with Q and right with E
+fn cycle_toolbar(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut toolbar: ResMut<Toolbar>,
+    mut commands: Commands,
+) {
+    let left = keyboard.just_pressed(KeyCode::KeyQ);
+    let right = keyboard.just_pressed(KeyCode::KeyE);
+    if left == right || toolbar.buttons.is_empty() {
+        return;
+    }
+
+    let new_selected = adjacent_slot(&toolbar, right);
+    select_toolbar_slot(&mut toolbar, &mut commands, new_selected);
+}
+
+/// Return the slot next to the selected one, wrapping around at the ends of the toolbar. The
+/// toolbar must have at least one slot.
+fn adjacent_slot(toolbar: &Toolbar, right: bool) -> usize {
+    let slot_count = toolbar.buttons.len();
+    match right {
+        true => (toolbar.selected + 1) % slot_count,
+        false => (toolbar.selected + slot_count - 1) % slot_count,
+    }
+}
+
+/// Move the toolbar selection to a new slot and shift the highlighted border along with it
+fn select_toolbar_slot(toolbar: &mut Toolbar, commands: &mut Commands, new_selected: usize) {
+    // The old selection may be past the end of a freshly rebuilt toolbar, so it can be missing
+    if let Some(&old_button) = toolbar.buttons.get(toolbar.selected) {
+        commands
+            .entity(old_button)
+            .insert(BorderColor::from(Srgba::new(0., 0., 0., 0.6)));
+    }
     toolbar.selected = new_selected;
     commands
         .entity(toolbar.buttons.get(toolbar.selected).unwrap().to_owned())
         .insert(BorderColor::from(Srgba::new(0., 0., 0., 1.)));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_abbreviated_to_fit() {
+        let cases = [
+            (5, "5"),
+            (999, "999"),
+            (1_000, "1.0k"),
+            (1_200, "1.2k"),
+            (1_299, "1.2k"),
+            (9_999, "9.9k"),
+            (10_000, "10k"),
+            (999_999, "999k"),
+            (1_000_000, "999k+"),
+        ];
+        for (count, expected) in cases {
+            assert_eq!(format_count(count), expected, "count {count}");
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use bevy::prelude::*;
+use terrustia::{GameMap, TerrustiaGamePlugin, TileData, TileDestroyed, inventory::Inventory};
+
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(TerrustiaGamePlugin {
+        headless: true,
+        ..default()
+    });
+    // Build the world and put the player on its surface
+    app.update();
+    app.update();
+    app
+}
+
+#[test]
+fn breaking_a_tile_removes_it_and_drops_its_item() {
+    let mut app = headless_app();
+
+    // The player spawns standing on the grass tile at the top of column 0, which drops dirt
+    let tile = app.world().resource::<GameMap>().tile_at((0, 0)).unwrap();
+    assert!(app.world().get::<TileData>(tile).unwrap().is_solid());
+
+    // Each trigger is one tick of holding the mouse on the tile, so keep going until it breaks
+    for _ in 0..100 {
+        app.world_mut().trigger_targets(TileDestroyed, tile);
+    }
+    // Let the inventory pick up the drop
+    app.update();
+
+    let tile_data = app.world().get::<TileData>(tile).unwrap();
+    assert!(!tile_data.is_solid());
+    assert_eq!(tile_data.fg_id(), 0);
+
+    let mut inventories = app.world_mut().query::<&Inventory>();
+    let inventory = inventories.single(app.world()).unwrap();
+    let dirt = inventory.0[0].unwrap();
+    assert_eq!((dirt.item_id, dirt.count), (1, 1));
+}